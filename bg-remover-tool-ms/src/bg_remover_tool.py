@@ -1,10 +1,11 @@
 import random
+from io import BytesIO
 
 from PIL import Image, ImageEnhance
 import rembg
 import numpy as np
-import os
 
+from . import storage
 from .core.tool import Tool
 from .bg_remover_request_message import BgRemoverParameters
 
@@ -19,7 +20,7 @@ class BgRemoverTool(Tool):
         Args:
             parameters (BgRemoverParameters): bg-remover parameters.
         """
-        input_image = Image.open(parameters.inputImageURI).convert("RGBA")
+        input_image = Image.open(BytesIO(storage.read(parameters.inputImageURI))).convert("RGBA")
 
         input_array = np.array(input_image)
 
@@ -29,8 +30,6 @@ class BgRemoverTool(Tool):
 
         final_image = output_image.convert("RGB")
         
-        output_dir = os.path.dirname(parameters.outputImageURI)
-        if output_dir and not os.path.exists(output_dir):
-            os.makedirs(output_dir)
-
-        final_image.save(parameters.outputImageURI)
+        output = BytesIO()
+        final_image.save(output, format="PNG")
+        storage.write(parameters.outputImageURI, output.getvalue())
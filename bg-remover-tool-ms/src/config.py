@@ -13,3 +13,9 @@ RABBITMQ_RESULTS_ROUTING_KEY = os.getenv("RABBITMQ_RESULTS_ROUTING_KEY", "result
 PICTURAS_LOG_LEVEL = os.getenv("PICTURAS_LOG_LEVEL", "INFO")
 PICTURAS_MS_NAME = os.getenv("PICTURAS_MS_NAME", "picturas-bg-remover-tool-ms")
 PICTURAS_NUM_THREADS = os.getenv("PICTURAS_NUM_THREADS", 4)
+
+# The S3-compatible storage of `s3://` URIs, e.g. `http://minio:9000`, AWS if no endpoint is given
+S3_ENDPOINT = os.getenv("S3_ENDPOINT")
+S3_REGION = os.getenv("S3_REGION", "us-east-1")
+S3_ACCESS_KEY = os.getenv("S3_ACCESS_KEY")
+S3_SECRET_KEY = os.getenv("S3_SECRET_KEY")
@@ -0,0 +1,107 @@
+import datetime
+import hashlib
+import hmac
+import os
+import urllib.parse
+import urllib.request
+
+from .config import S3_ENDPOINT, S3_REGION, S3_ACCESS_KEY, S3_SECRET_KEY
+
+
+def read(uri: str) -> bytes:
+    """
+    Read the file an URI points to, either a `file://` URI, a plain path or an `s3://<bucket>/<key>` URI.
+    """
+    bucket_key = _s3_location(uri)
+    if bucket_key:
+        return _s3_request("GET", *bucket_key)
+
+    with open(_local_path(uri), "rb") as file:
+        return file.read()
+
+
+def write(uri: str, data: bytes):
+    """
+    Write a file where an URI points to, either a `file://` URI, a plain path or an `s3://<bucket>/<key>` URI.
+    """
+    bucket_key = _s3_location(uri)
+    if bucket_key:
+        _s3_request("PUT", *bucket_key, body=data)
+        return
+
+    path = _local_path(uri)
+    directory = os.path.dirname(path)
+    if directory:
+        os.makedirs(directory, exist_ok=True)
+
+    with open(path, "wb") as file:
+        file.write(data)
+
+
+def _local_path(uri: str) -> str:
+    if uri.startswith("file://"):
+        return uri[len("file://"):]
+    if "://" in uri:
+        raise ValueError(f"Unsupported image URI: {uri}")
+    return uri
+
+
+def _s3_location(uri: str) -> tuple[str, str] | None:
+    if not uri.startswith("s3://"):
+        return None
+
+    bucket, _, key = uri[len("s3://"):].partition("/")
+    if not bucket or not key:
+        raise ValueError(f"Invalid S3 URI: {uri}")
+    return bucket, key
+
+
+def _s3_request(method: str, bucket: str, key: str, body: bytes = b"") -> bytes:
+    """
+    Send a path-style request to the S3-compatible storage, which most of them support,
+    signed with AWS Signature Version 4 if credentials are configured.
+    """
+    endpoint = urllib.parse.urlsplit(S3_ENDPOINT or f"https://s3.{S3_REGION}.amazonaws.com")
+    path = f"{endpoint.path.rstrip('/')}/{bucket}/{urllib.parse.quote(key)}"
+
+    headers = {
+        "host": endpoint.netloc,
+        "x-amz-content-sha256": hashlib.sha256(body).hexdigest(),
+        "x-amz-date": datetime.datetime.now(datetime.timezone.utc).strftime("%Y%m%dT%H%M%SZ"),
+    }
+    if S3_ACCESS_KEY and S3_SECRET_KEY:
+        headers["authorization"] = _authorization(method, path, headers, S3_REGION, S3_ACCESS_KEY, S3_SECRET_KEY)
+
+    request = urllib.request.Request(
+        f"{endpoint.scheme}://{endpoint.netloc}{path}",
+        data=body if method == "PUT" else None,
+        headers=headers,
+        method=method,
+    )
+    with urllib.request.urlopen(request) as response:
+        return response.read()
+
+
+def _authorization(method: str, path: str, headers: dict[str, str], region: str, access_key: str,
+                   secret_key: str) -> str:
+    """
+    The `Authorization` header of a request without query string, signing all its headers.
+    """
+    amz_date = headers["x-amz-date"]
+    date = amz_date[:8]
+    scope = f"{date}/{region}/s3/aws4_request"
+
+    names = sorted(headers)
+    signed_headers = ";".join(names)
+    canonical_headers = "".join(f"{name}:{headers[name].strip()}\n" for name in names)
+    canonical_request = "\n".join(
+        [method, path, "", canonical_headers, signed_headers, headers["x-amz-content-sha256"]])
+    string_to_sign = "\n".join(
+        ["AWS4-HMAC-SHA256", amz_date, scope, hashlib.sha256(canonical_request.encode()).hexdigest()])
+
+    signing_key = f"AWS4{secret_key}".encode()
+    for part in (date, region, "s3", "aws4_request"):
+        signing_key = hmac.new(signing_key, part.encode(), hashlib.sha256).digest()
+    signature = hmac.new(signing_key, string_to_sign.encode(), hashlib.sha256).hexdigest()
+
+    return f"AWS4-HMAC-SHA256 Credential={access_key}/{scope}, SignedHeaders={signed_headers}, Signature={signature}"
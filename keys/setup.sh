@@ -1,4 +1,5 @@
 ssh-keygen -t rsa -b 4096 -m PEM -f access.key
 openssl rsa -in access.key -pubout -outform PEM -out access.key.pub
 ssh-keygen -t rsa -b 4096 -m PEM -f refresh.key
-openssl rsa -in refresh.key -pubout -outform PEM -out refresh.key.pub
\ No newline at end of file
+openssl rsa -in refresh.key -pubout -outform PEM -out refresh.key.pub
+openssl rand -hex 32 > internal.key
\ No newline at end of file
@@ -0,0 +1,24 @@
+CREATE TYPE job_step_status AS ENUM ('queued', 'running', 'succeeded', 'failed');
+
+CREATE TABLE IF NOT EXISTS jobs
+(
+    id         UUID PRIMARY KEY,
+    project_id UUID                                  NOT NULL REFERENCES projects (id) ON DELETE CASCADE,
+    user_id    UUID                                  NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS job_steps
+(
+    id                UUID PRIMARY KEY, -- also the id of the image version created by this step
+    job_id            UUID            NOT NULL REFERENCES jobs (id) ON DELETE CASCADE,
+    original_image_id UUID            NOT NULL REFERENCES images (id) ON DELETE CASCADE,
+    tool_id           UUID            NOT NULL REFERENCES tools (id) ON DELETE CASCADE,
+    position          INTEGER         NOT NULL,
+    procedure         VARCHAR(255)    NOT NULL,
+    parameters        JSONB           NOT NULL,
+    status            job_step_status NOT NULL,
+    message_id        UUID UNIQUE -- the id of the request sent to the tool, set once the step is published
+);
+
+CREATE INDEX IF NOT EXISTS job_steps_chain_idx ON job_steps (job_id, original_image_id, position);
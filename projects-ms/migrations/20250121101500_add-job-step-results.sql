@@ -0,0 +1,6 @@
+ALTER TABLE job_steps
+    ADD COLUMN started_at      TIMESTAMPTZ,
+    ADD COLUMN finished_at     TIMESTAMPTZ,
+    ADD COLUMN processing_time DOUBLE PRECISION, -- seconds, as reported by the tool
+    ADD COLUMN error_code      VARCHAR(255),
+    ADD COLUMN error_message   TEXT;
@@ -0,0 +1,4 @@
+ALTER TYPE job_step_status ADD VALUE IF NOT EXISTS 'cancelled';
+
+ALTER TABLE jobs
+    ADD COLUMN cancelled_at TIMESTAMPTZ;
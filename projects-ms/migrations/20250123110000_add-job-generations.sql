@@ -0,0 +1,15 @@
+ALTER TYPE job_step_status ADD VALUE IF NOT EXISTS 'superseded';
+
+-- each apply of a project gets a new generation, results of older generations are rejected
+ALTER TABLE jobs
+    ADD COLUMN generation INTEGER NOT NULL DEFAULT 0;
+
+UPDATE jobs j
+SET generation = g.generation
+FROM (SELECT id, ROW_NUMBER() OVER (PARTITION BY project_id ORDER BY created_at) AS generation FROM jobs) g
+WHERE j.id = g.id;
+
+ALTER TABLE jobs
+    ALTER COLUMN generation DROP DEFAULT;
+
+CREATE UNIQUE INDEX IF NOT EXISTS jobs_project_generation_idx ON jobs (project_id, generation);
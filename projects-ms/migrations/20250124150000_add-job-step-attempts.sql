@@ -0,0 +1,2 @@
+ALTER TABLE job_steps
+    ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0; -- amount of times the step was retried
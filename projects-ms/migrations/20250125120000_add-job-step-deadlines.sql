@@ -0,0 +1,6 @@
+ALTER TYPE job_step_status ADD VALUE IF NOT EXISTS 'timed_out';
+
+ALTER TABLE job_steps
+    ADD COLUMN deadline TIMESTAMPTZ; -- when a running step is considered lost if no result arrived
+
+CREATE INDEX IF NOT EXISTS job_steps_running_deadline_idx ON job_steps (deadline) WHERE status = 'running';
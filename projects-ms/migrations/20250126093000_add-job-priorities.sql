@@ -0,0 +1,7 @@
+ALTER TABLE jobs
+    ADD COLUMN priority SMALLINT NOT NULL DEFAULT 0; -- priority of the tool requests, based on the subscription of the user
+
+ALTER TABLE job_steps
+    ADD COLUMN available_at TIMESTAMPTZ; -- a retried step isn't published before this
+
+CREATE INDEX IF NOT EXISTS job_steps_status_idx ON job_steps (status);
@@ -0,0 +1,13 @@
+-- preview jobs run the tools on a downscaled proxy of the images and don't replace the image versions
+ALTER TABLE jobs
+    ADD COLUMN preview BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE IF NOT EXISTS preview_versions
+(
+    id                UUID PRIMARY KEY REFERENCES job_steps (id) ON DELETE CASCADE,
+    project_id        UUID                                  NOT NULL REFERENCES projects (id) ON DELETE CASCADE,
+    original_image_id UUID                                  NOT NULL REFERENCES images (id) ON DELETE CASCADE,
+    tool_id           UUID                                  NOT NULL REFERENCES tools (id) ON DELETE CASCADE,
+    text_result       TEXT, -- OCR results, if the tool is OCR, or any textual output
+    created_at        TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
@@ -0,0 +1,8 @@
+-- hash of the procedure and parameters of a tool and of the hash of the tool before it,
+-- image versions of a tool are reused while its hash doesn't change
+ALTER TABLE tools
+    ADD COLUMN hash VARCHAR(64);
+
+-- the image version the first step of an image chain takes as input, when the tools before it were reused
+ALTER TABLE job_steps
+    ADD COLUMN input_version_id UUID REFERENCES image_versions (id) ON DELETE SET NULL;
@@ -0,0 +1,18 @@
+-- sha256 of the uploaded bytes, images uploaded before don't use the result cache
+ALTER TABLE images
+    ADD COLUMN content_hash VARCHAR(64);
+
+-- results of the tools shared between every project
+CREATE TABLE IF NOT EXISTS result_cache
+(
+    key         VARCHAR(64) PRIMARY KEY, -- hash of the input image and of every step applied to it
+    text_result TEXT,
+    ref_count   INTEGER                               NOT NULL, -- image versions using the result
+    created_at  TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+ALTER TABLE image_versions
+    ADD COLUMN cache_key VARCHAR(64) REFERENCES result_cache (key);
+
+ALTER TABLE job_steps
+    ADD COLUMN cache_key VARCHAR(64);
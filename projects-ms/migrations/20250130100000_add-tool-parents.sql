@@ -0,0 +1,22 @@
+-- the tools of a project form a tree, each tool takes the output of its parent as input,
+-- or the original image if it has none
+ALTER TABLE tools
+    ADD COLUMN parent_id UUID REFERENCES tools (id) ON DELETE CASCADE;
+
+UPDATE tools t
+SET parent_id = p.parent_id
+FROM (SELECT id, LAG(id) OVER (PARTITION BY project_id ORDER BY position) AS parent_id FROM tools) p
+WHERE t.id = p.id;
+
+-- the step whose output is the input of the step
+ALTER TABLE job_steps
+    ADD COLUMN parent_step_id UUID REFERENCES job_steps (id) ON DELETE CASCADE;
+
+UPDATE job_steps s
+SET parent_step_id = p.parent_step_id
+FROM (SELECT id,
+             LAG(id) OVER (PARTITION BY job_id, original_image_id ORDER BY position) AS parent_step_id
+      FROM job_steps) p
+WHERE s.id = p.id;
+
+CREATE INDEX IF NOT EXISTS job_steps_parent_step_idx ON job_steps (parent_step_id);
@@ -0,0 +1,14 @@
+-- the condition the input image must match for a tool to run
+ALTER TABLE tools
+    ADD COLUMN condition JSONB;
+
+ALTER TABLE job_steps
+    ADD COLUMN condition JSONB,
+    ADD COLUMN skipped   BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- skipped steps pass their input through as their output
+ALTER TABLE image_versions
+    ADD COLUMN skipped BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE preview_versions
+    ADD COLUMN skipped BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,22 @@
+-- tool lists saved by a user to be instantiated into any of their projects
+CREATE TABLE IF NOT EXISTS presets
+(
+    id         UUID PRIMARY KEY,
+    user_id    UUID                                  NOT NULL,
+    name       VARCHAR(255)                          NOT NULL,
+    version    INTEGER                               NOT NULL, -- the latest version
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    updated_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS presets_user_idx ON presets (user_id);
+
+-- every change to the tools of a preset is kept as a new version
+CREATE TABLE IF NOT EXISTS preset_versions
+(
+    preset_id  UUID                                  NOT NULL REFERENCES presets (id) ON DELETE CASCADE,
+    version    INTEGER                               NOT NULL,
+    tools      JSONB                                 NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    PRIMARY KEY (preset_id, version)
+);
@@ -0,0 +1,10 @@
+-- concurrent additions of tools could take the same position
+UPDATE tools t
+SET position = p.position
+FROM (SELECT id, ROW_NUMBER() OVER (PARTITION BY project_id ORDER BY position, id) AS position
+      FROM tools) p
+WHERE t.id = p.id;
+
+-- deferred, so the positions of several tools can be rewritten in a transaction
+ALTER TABLE tools
+    ADD CONSTRAINT tools_project_position_key UNIQUE (project_id, position) DEFERRABLE INITIALLY DEFERRED;
@@ -0,0 +1,3 @@
+-- disabled tools are kept in the project but bypassed when applying the tools
+ALTER TABLE tools
+    ADD COLUMN enabled BOOLEAN NOT NULL DEFAULT TRUE;
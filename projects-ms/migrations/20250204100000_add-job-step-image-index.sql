@@ -0,0 +1,2 @@
+-- a job only supersedes the steps of the older generations on the same images
+CREATE INDEX IF NOT EXISTS job_steps_original_image_idx ON job_steps (original_image_id);
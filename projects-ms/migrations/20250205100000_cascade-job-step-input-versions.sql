@@ -0,0 +1,4 @@
+-- a step whose input version is deleted can't run anymore, it goes along with its descendants
+ALTER TABLE job_steps
+    DROP CONSTRAINT job_steps_input_version_id_fkey,
+    ADD CONSTRAINT job_steps_input_version_id_fkey FOREIGN KEY (input_version_id) REFERENCES image_versions (id) ON DELETE CASCADE;
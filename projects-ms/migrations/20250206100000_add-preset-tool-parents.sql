@@ -0,0 +1,6 @@
+-- the tools of a preset keep the index of the tool they take the output of, the tools saved so
+-- far each took the output of the one before it
+UPDATE preset_versions
+SET tools = (SELECT COALESCE(jsonb_agg(tool || jsonb_build_object('parent', CASE WHEN index = 1 THEN NULL ELSE index - 2 END)
+                                       ORDER BY index), '[]'::jsonb)
+             FROM jsonb_array_elements(tools) WITH ORDINALITY AS t(tool, index));
@@ -0,0 +1,47 @@
+use crate::error::Result;
+use crate::tool::cache;
+use crate::AppState;
+use std::path::PathBuf;
+
+/// The files of rows deleted in a transaction, only removed once the transaction commits, so a
+/// transaction that fails never leaves rows pointing to missing files.
+#[derive(Debug, Default)]
+pub struct Cleanup {
+    files: Vec<PathBuf>,
+    folders: Vec<PathBuf>,
+    cache_keys: Vec<String>,
+}
+
+impl Cleanup {
+    pub fn remove_file(&mut self, path: PathBuf) {
+        self.files.push(path);
+    }
+
+    pub fn remove_folder(&mut self, path: PathBuf) {
+        self.folders.push(path);
+    }
+
+    /// Releases a reference to a cached result, its file being removed if no longer used.
+    pub fn release_cached_result(&mut self, key: String) {
+        self.cache_keys.push(key);
+    }
+
+    pub fn extend(&mut self, other: Cleanup) {
+        self.files.extend(other.files);
+        self.folders.extend(other.folders);
+        self.cache_keys.extend(other.cache_keys);
+    }
+
+    /// Removes the files, to be called after the transaction that deleted their rows committed.
+    pub async fn run(self, state: &AppState) -> Result<()> {
+        // the rows are already gone, so a file that can't be removed is only left behind
+        for file in self.files {
+            let _ = state.storage.delete(&file).await;
+        }
+        for folder in self.folders {
+            let _ = state.storage.delete_folder(&folder).await;
+        }
+
+        cache::release(&self.cache_keys, state).await
+    }
+}
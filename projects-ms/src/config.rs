@@ -1,8 +1,8 @@
 use crate::tool::amqp::rabbit_controller::ToolQueue;
-use crate::AppState;
 use clap::Parser;
 use jsonwebtoken::DecodingKey;
 use std::path::PathBuf;
+use std::time::Duration;
 use uuid::Uuid;
 
 #[derive(Parser)]
@@ -29,18 +29,195 @@ pub struct Config {
     pub rabbitmq_results_exchange: String,
     #[arg(long, env)]
     pub rabbitmq_results_routing_key: String,
+    #[arg(long, env, default_value = "picturas.tools.dead-letter")]
+    pub rabbitmq_dead_letter_exchange: String,
+    /// The exchange the tools announce their procedures to.
+    #[arg(long, env, default_value = "picturas.tools.control")]
+    pub rabbitmq_control_exchange: String,
     #[arg(long, env)]
     pub bind_ip: String,
     #[arg(long, env, default_value_t = 8080)]
     pub bind_port: u16,
-    #[arg(long, env)]
+    /// Where the images and the results of the tools are stored.
+    #[arg(long, env, value_enum, default_value_t = StorageKind::File)]
+    pub picturas_storage: StorageKind,
+    /// The folder of the `file` storage, which the tools must mount at the same path.
+    #[arg(long, env, default_value = "/images")]
     pub picturas_image_folder: PathBuf,
+    /// The folder uploads are written to while they are received.
+    #[arg(long, env, default_value_os_t = std::env::temp_dir())]
+    pub picturas_upload_folder: PathBuf,
+    /// The most entries a zip archive of images can have.
+    #[arg(long, env, default_value_t = 1000)]
+    pub picturas_zip_max_entries: usize,
+    /// The largest size, in bytes, of an image extracted from a zip archive.
+    #[arg(long, env, default_value_t = 100 * 1024 * 1024)]
+    pub picturas_zip_max_entry_size: u64,
+    /// The largest ratio between the extracted and the compressed size of an entry of a zip
+    /// archive.
+    #[arg(long, env, default_value_t = 100)]
+    pub picturas_zip_max_compression_ratio: u64,
+    /// The endpoint of the `s3` storage, e.g. `http://minio:9000`, the one of AWS if none is
+    /// given.
+    #[arg(long, env)]
+    pub s3_endpoint: Option<String>,
+    #[arg(long, env, default_value = "us-east-1")]
+    pub s3_region: String,
+    #[arg(long, env)]
+    pub s3_bucket: Option<String>,
+    #[arg(long, env)]
+    pub s3_access_key: Option<String>,
+    #[arg(long, env)]
+    pub s3_secret_key: Option<String>,
     #[arg(long, env)]
     pub picturas_public_url: String,
+    /// The secret the download URLs are signed with, so they work without the access token.
+    #[arg(long, env)]
+    pub picturas_url_signing_key: String,
+    /// Seconds a signed download URL stays valid.
+    #[arg(long, env, default_value_t = 3600)]
+    pub picturas_signed_url_ttl_secs: u64,
+    /// Whether a signed download URL gives access to its file alone or to every file of the
+    /// project.
+    #[arg(long, env, value_enum, default_value_t = SignedUrlScope::Image)]
+    pub picturas_signed_url_scope: SignedUrlScope,
     #[arg(long, env, use_value_delimiter = true, value_parser = parse_tool_queue)]
     pub picturas_available_tools: Vec<ToolQueue>,
     #[arg(long, env, value_parser = load_decoding_key_from_file)]
     pub access_token_public_key: DecodingKey,
+    /// Retries of a failed step for specific procedures, e.g. `ocr:3,bgRemover:1`.
+    #[arg(long, env, use_value_delimiter = true, value_parser = parse_procedure_retries)]
+    pub picturas_tool_retries: Vec<ProcedureRetries>,
+    #[arg(long, env, default_value_t = 2)]
+    pub picturas_default_tool_retries: u32,
+    /// Error codes returned by the tools that are worth retrying.
+    /// `TIMEOUT` is used for steps that didn't get a result before their deadline.
+    #[arg(
+        long,
+        env,
+        use_value_delimiter = true,
+        default_value = "IMAGE_OPEN_ERROR,IMAGE_SAVE_ERROR,IMAGE_SAVE_CREATE_FOLDERS_ERROR,TIMEOUT"
+    )]
+    pub picturas_retryable_error_codes: Vec<String>,
+    /// Delay before the first retry, doubled on every following attempt.
+    #[arg(long, env, default_value_t = 1000)]
+    pub picturas_retry_backoff_ms: u64,
+    /// Seconds a specific procedure has to answer a request, e.g. `ocr:120,bgRemover:180`.
+    #[arg(long, env, use_value_delimiter = true, value_parser = parse_procedure_timeout)]
+    pub picturas_tool_timeouts: Vec<ProcedureTimeout>,
+    #[arg(long, env, default_value_t = 30)]
+    pub picturas_default_tool_timeout_secs: u64,
+    /// How often running steps are checked for a passed deadline.
+    #[arg(long, env, default_value_t = 5)]
+    pub picturas_timeout_sweep_interval_secs: u64,
+    /// The highest priority a message of the tool queues can have.
+    #[arg(long, env, default_value_t = 10)]
+    pub rabbitmq_max_priority: u8,
+    /// Priority of the tool requests of users with a premium subscription, the others have none.
+    #[arg(long, env, default_value_t = 5)]
+    pub picturas_premium_priority: u8,
+    /// Steps of the same user that can be waiting for a tool at the same time.
+    #[arg(long, env, default_value_t = 16)]
+    pub picturas_max_in_flight_steps_per_user: i64,
+    /// The largest side, in pixels, of the proxy images used by preview jobs.
+    #[arg(long, env, default_value_t = 512)]
+    pub picturas_preview_max_size: u32,
+    /// Seconds a procedure stays in the tool catalogue after the last announcement of a tool
+    /// handling it.
+    #[arg(long, env, default_value_t = 90)]
+    pub picturas_catalogue_expiration_secs: u64,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum StorageKind {
+    /// A folder of the local filesystem.
+    File,
+    /// A bucket of an S3-compatible object storage.
+    S3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignedUrlScope {
+    /// The URL only gives access to the file it points to.
+    Image,
+    /// The URL gives access to every file of the project of the file it points to.
+    Project,
+}
+
+impl SignedUrlScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SignedUrlScope::Image => "image",
+            SignedUrlScope::Project => "project",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProcedureRetries {
+    pub procedure: String,
+    pub max_retries: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProcedureTimeout {
+    pub procedure: String,
+    pub timeout: Duration,
+}
+
+impl Config {
+    /// Whether a step that failed with `error_code` after `attempts` retries should be retried.
+    pub fn should_retry(&self, procedure: &str, error_code: &str, attempts: i32) -> bool {
+        let max_retries = self
+            .picturas_tool_retries
+            .iter()
+            .find(|retries| retries.procedure == procedure)
+            .map_or(self.picturas_default_tool_retries, |retries| {
+                retries.max_retries
+            });
+
+        self.picturas_retryable_error_codes
+            .iter()
+            .any(|code| code == error_code)
+            && attempts < max_retries as i32
+    }
+
+    pub fn retry_backoff(&self, attempts: i32) -> Duration {
+        let multiplier = 2u64.pow(attempts.clamp(0, 10) as u32);
+        Duration::from_millis(self.picturas_retry_backoff_ms * multiplier)
+    }
+
+    /// The time a tool has to answer a request for `procedure` before the step times out.
+    pub fn tool_timeout(&self, procedure: &str) -> Duration {
+        self.picturas_tool_timeouts
+            .iter()
+            .find(|timeout| timeout.procedure == procedure)
+            .map_or(
+                Duration::from_secs(self.picturas_default_tool_timeout_secs),
+                |timeout| timeout.timeout,
+            )
+    }
+}
+
+fn parse_procedure_retries(src: &str) -> Result<ProcedureRetries, String> {
+    let (procedure, max_retries) = src
+        .split_once(':')
+        .ok_or_else(|| format!("expected procedure:retries, got {src}"))?;
+    Ok(ProcedureRetries {
+        procedure: procedure.to_string(),
+        max_retries: max_retries.parse().map_err(|e| format!("{e}"))?,
+    })
+}
+
+fn parse_procedure_timeout(src: &str) -> Result<ProcedureTimeout, String> {
+    let (procedure, seconds) = src
+        .split_once(':')
+        .ok_or_else(|| format!("expected procedure:seconds, got {src}"))?;
+    Ok(ProcedureTimeout {
+        procedure: procedure.to_string(),
+        timeout: Duration::from_secs(seconds.parse().map_err(|e| format!("{e}"))?),
+    })
 }
 
 fn parse_tool_queue(src: &str) -> Result<ToolQueue, String> {
@@ -55,38 +232,73 @@ fn load_decoding_key_from_file(path: &str) -> Result<DecodingKey, std::io::Error
     Ok(DecodingKey::from_rsa_pem(&std::fs::read(path)?).expect("Failed to read RSA key"))
 }
 
-pub fn generate_image_version_folder_uri(project_uuid: Uuid, state: &AppState) -> PathBuf {
-    state
-        .config
-        .picturas_image_folder
-        .join(project_uuid.to_string())
-        .join("output")
+// the keys of the files in the storage, relative to its root
+
+pub fn generate_project_folder_uri(project_uuid: Uuid) -> PathBuf {
+    PathBuf::from(project_uuid.to_string())
+}
+
+pub fn generate_image_version_folder_uri(project_uuid: Uuid) -> PathBuf {
+    PathBuf::from(project_uuid.to_string()).join("output")
 }
 
 pub fn generate_image_version_output_uri(
     project_uuid: Uuid,
     original_image_uuid: Uuid,
     new_image_uuid: Uuid,
-    state: &AppState,
 ) -> PathBuf {
-    generate_image_version_folder_uri(project_uuid, state)
+    generate_image_version_folder_uri(project_uuid)
+        .join(original_image_uuid.to_string())
+        .join(new_image_uuid.to_string())
+        .with_extension("png")
+}
+
+pub fn generate_cached_result_uri(cache_key: &str) -> PathBuf {
+    PathBuf::from("cache").join(cache_key).with_extension("png")
+}
+
+pub fn generate_preview_folder_uri(project_uuid: Uuid) -> PathBuf {
+    PathBuf::from(project_uuid.to_string()).join("previews")
+}
+
+pub fn generate_preview_output_uri(
+    project_uuid: Uuid,
+    original_image_uuid: Uuid,
+    new_image_uuid: Uuid,
+) -> PathBuf {
+    generate_preview_folder_uri(project_uuid)
         .join(original_image_uuid.to_string())
         .join(new_image_uuid.to_string())
         .with_extension("png")
 }
 
-pub fn generate_image_uri(
+pub fn generate_live_preview_folder_uri(project_uuid: Uuid, user_uuid: Uuid) -> PathBuf {
+    PathBuf::from(project_uuid.to_string())
+        .join("live")
+        .join(user_uuid.to_string())
+}
+
+pub fn generate_live_preview_output_uri(
     project_uuid: Uuid,
-    image_uuid: Uuid,
-    image_name: &str,
-    state: &AppState,
+    user_uuid: Uuid,
+    preview_uuid: Uuid,
 ) -> PathBuf {
+    generate_live_preview_folder_uri(project_uuid, user_uuid)
+        .join(preview_uuid.to_string())
+        .with_extension("png")
+}
+
+pub fn generate_proxy_image_uri(project_uuid: Uuid, image_uuid: Uuid) -> PathBuf {
+    PathBuf::from(project_uuid.to_string())
+        .join("proxies")
+        .join(image_uuid.to_string())
+        .with_extension("png")
+}
+
+pub fn generate_image_uri(project_uuid: Uuid, image_uuid: Uuid, image_name: &str) -> PathBuf {
     let buf = PathBuf::from(image_name);
     let extension_from_name = buf.extension().unwrap_or_default();
-    state
-        .config
-        .picturas_image_folder
-        .join(project_uuid.to_string())
+    PathBuf::from(project_uuid.to_string())
         .join(image_uuid.to_string())
         .with_extension(extension_from_name)
 }
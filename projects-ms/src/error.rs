@@ -1,4 +1,6 @@
+use crate::storage::StorageError;
 use crate::tool::amqp::rabbit_controller::RabbitMqControllerError;
+use crate::tool::validation::ValidationErrors;
 use axum::extract::multipart::MultipartError;
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
@@ -19,6 +21,8 @@ pub enum AppError {
     MultipartMissing(&'static str),
     #[error("io error: {0}")]
     Io(#[from] std::io::Error),
+    #[error("storage error: {0}")]
+    Storage(#[from] StorageError),
     #[error("rabbitmq controller error: {0}")]
     RabbitMq(#[from] RabbitMqControllerError),
     #[error("not an image: {0}")]
@@ -33,6 +37,18 @@ pub enum AppError {
     ZipError(#[from] zip::result::ZipError),
     #[error("invalid zip file")]
     InvalidZip,
+    #[error("unsafe zip file: {0}")]
+    UnsafeZip(String),
+    #[error("json error: {0}")]
+    SerdeJson(#[from] serde_json::Error),
+    #[error("image error: {0}")]
+    Image(#[from] image::ImageError),
+    #[error("invalid pipeline: {0}")]
+    InvalidPipeline(&'static str),
+    #[error("invalid pipeline document: {0}")]
+    InvalidPipelineDocument(String),
+    #[error("validation error: {0}")]
+    Validation(#[from] ValidationErrors),
     #[error("internal error")]
     InternalError,
 }
@@ -40,6 +56,8 @@ pub enum AppError {
 #[derive(Debug, serde::Serialize)]
 struct ErrorBody {
     error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<serde_json::Value>,
 }
 
 impl IntoResponse for AppError {
@@ -48,6 +66,7 @@ impl IntoResponse for AppError {
         let status = match self {
             AppError::Sqlx(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::RabbitMq(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::EntityNotFound => StatusCode::NOT_FOUND,
             AppError::Multipart(_) => StatusCode::BAD_REQUEST,
@@ -58,15 +77,27 @@ impl IntoResponse for AppError {
             AppError::ZipError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::Forbidden => StatusCode::FORBIDDEN,
             AppError::InvalidZip => StatusCode::BAD_REQUEST,
+            AppError::UnsafeZip(_) => StatusCode::BAD_REQUEST,
+            AppError::SerdeJson(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::Image(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::InvalidPipeline(_) => StatusCode::BAD_REQUEST,
+            AppError::InvalidPipelineDocument(_) => StatusCode::BAD_REQUEST,
+            AppError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
             AppError::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
         };
 
+        let details = match &self {
+            AppError::Validation(errors) => serde_json::to_value(errors).ok(),
+            _ => None,
+        };
+
         let error = match self {
             AppError::Sqlx(_) => "Database error".to_string(),
             AppError::EntityNotFound => "Entity not found".to_string(),
             AppError::Multipart(err) => format!("Multipart error: {err}"),
             AppError::MultipartMissing(missing) => format!("Missing multipart field: {missing}"),
             AppError::Io(_) => "Internal IO error".to_string(),
+            AppError::Storage(_) => "Internal storage error".to_string(),
             AppError::RabbitMq(_) => "Internal controller error".to_string(),
             AppError::NotAnImage(content_type) => format!("Not an image: {content_type}"),
             AppError::Unauthorized => "Unauthorized".to_string(),
@@ -74,10 +105,18 @@ impl IntoResponse for AppError {
             AppError::ZipError(_) => "Internal zip error".to_string(),
             AppError::Forbidden => "No permission".to_string(),
             AppError::InvalidZip => "Invalid zip file".to_string(),
+            AppError::UnsafeZip(reason) => format!("Zip file rejected: {reason}"),
+            AppError::SerdeJson(_) => "Internal serialization error".to_string(),
+            AppError::Image(_) => "Internal image processing error".to_string(),
+            AppError::InvalidPipeline(reason) => format!("Invalid pipeline: {reason}"),
+            AppError::InvalidPipelineDocument(reason) => {
+                format!("Invalid pipeline document: {reason}")
+            }
+            AppError::Validation(_) => "Validation error".to_string(),
             AppError::InternalError => "Internal error".to_string(),
         };
 
-        let body = ErrorBody { error };
+        let body = ErrorBody { error, details };
 
         (status, Json(body)).into_response()
     }
@@ -1,16 +1,15 @@
-use crate::error::Result;
+use crate::error::{AppError, Result};
 use crate::image::model::Image;
-use crate::AppState;
-use axum::body::Bytes;
-use tokio::fs::File;
-use tokio::io::{AsyncWriteExt, BufWriter};
+use crate::image::upload::UploadedFile;
+use crate::{config, tool, AppState};
+use std::path::PathBuf;
 use tracing::info;
 use uuid::Uuid;
 
 pub async fn create_image(
     project_uuid: Uuid,
     image_name: String,
-    image_bytes: Bytes,
+    upload: UploadedFile,
     state: &AppState,
 ) -> Result<Image> {
     info!("Creating image with name: {}", image_name);
@@ -20,22 +19,28 @@ pub async fn create_image(
         id: uuid,
         name: image_name,
         project_id: project_uuid,
+        content_hash: Some(upload.content_hash.clone()),
     };
 
-    let path = image.get_uri(state);
-    tokio::fs::create_dir_all(&path.parent().unwrap()).await?;
-    let file = File::create(&path).await?;
-    let mut writer = BufWriter::new(file);
-    writer.write_all(&image_bytes).await?;
+    let path = image.get_uri();
+    // the file is written before the row is inserted, and removed if the insertion fails, so a
+    // failure doesn't leave a row without a file
+    state.storage.write_file(&path, upload.path()).await?;
 
-    sqlx::query!(
-        "INSERT INTO images (id, name, project_id) VALUES ($1, $2, $3)",
+    let result = sqlx::query!(
+        "INSERT INTO images (id, name, project_id, content_hash) VALUES ($1, $2, $3, $4)",
         image.id,
         image.name,
-        image.project_id
+        image.project_id,
+        image.content_hash
     )
     .execute(&state.db_pool)
-    .await?;
+    .await;
+
+    if let Err(err) = result {
+        let _ = state.storage.delete(&path).await;
+        return Err(err.into());
+    }
 
     info!(
         id = ?image.id,
@@ -49,7 +54,7 @@ pub async fn get_original_images(project_uuid: Uuid, state: &AppState) -> Result
     info!("Fetching original images for project ID: {}", project_uuid);
     let images = sqlx::query_as!(
         Image,
-        "SELECT id, name, project_id FROM images WHERE project_id = $1",
+        "SELECT id, name, project_id, content_hash FROM images WHERE project_id = $1",
         project_uuid
     )
     .fetch_all(&state.db_pool)
@@ -59,30 +64,105 @@ pub async fn get_original_images(project_uuid: Uuid, state: &AppState) -> Result
     Ok(images)
 }
 
+pub async fn get_original_image(
+    project_uuid: Uuid,
+    image_uuid: Uuid,
+    state: &AppState,
+) -> Result<Option<Image>> {
+    let image = sqlx::query_as!(
+        Image,
+        "SELECT id, name, project_id, content_hash FROM images WHERE id = $1 AND project_id = $2",
+        image_uuid,
+        project_uuid
+    )
+    .fetch_optional(&state.db_pool)
+    .await?;
+
+    Ok(image)
+}
+
+/// Returns the path of the downscaled proxy of an image used by preview jobs,
+/// generating it the first time it is needed.
+pub async fn get_proxy_image_uri(image: &Image, state: &AppState) -> Result<PathBuf> {
+    let proxy_path = config::generate_proxy_image_uri(image.project_id, image.id);
+    if state.storage.exists(&proxy_path).await? {
+        return Ok(proxy_path);
+    }
+
+    let image_bytes = state.storage.read(&image.get_uri()).await?;
+    let max_size = state.config.picturas_preview_max_size;
+
+    let proxy_bytes = tokio::task::spawn_blocking(move || {
+        let original = ::image::load_from_memory(&image_bytes)?;
+        let proxy = if original.width() > max_size || original.height() > max_size {
+            original.thumbnail(max_size, max_size)
+        } else {
+            original
+        };
+
+        let mut proxy_bytes = Vec::new();
+        proxy.write_to(
+            &mut std::io::Cursor::new(&mut proxy_bytes),
+            ::image::ImageFormat::Png,
+        )?;
+        Ok::<_, ::image::ImageError>(proxy_bytes)
+    })
+    .await
+    .map_err(|_| AppError::InternalError)??;
+
+    state.storage.write(&proxy_path, proxy_bytes).await?;
+
+    info!(id = ?image.id, "Generated proxy image");
+
+    Ok(proxy_path)
+}
+
+/// Deletes an image along with its image versions, its files being removed once the rows are.
 pub async fn delete_image(
     image_uuid: Uuid,
     project_uuid: Uuid,
     state: &AppState,
 ) -> Result<Option<Image>> {
     info!("Deleting image with ID: {}", image_uuid);
+    let mut transaction = state.db_pool.begin().await?;
+
     let image = sqlx::query_as!(
         Image,
-        "SELECT id, name, project_id FROM images WHERE id = $1 AND project_id = $2",
+        "SELECT id, name, project_id, content_hash FROM images WHERE id = $1 AND project_id = $2 FOR UPDATE",
         image_uuid,
         project_uuid
     )
-    .fetch_optional(&state.db_pool)
+    .fetch_optional(&mut *transaction)
     .await?;
 
     let Some(image) = image else {
         return Ok(None);
     };
 
+    let image_versions = tool::controller::lock_image_versions(project_uuid, &mut transaction)
+        .await?
+        .into_iter()
+        .filter(|image_version| image_version.original_image_id == image.id)
+        .collect();
+    let mut cleanup =
+        tool::controller::delete_image_versions(image_versions, &mut transaction).await?;
+
+    // the job steps and preview versions of the image are deleted along with it
     sqlx::query!("DELETE FROM images WHERE id = $1", image.id)
-        .execute(&state.db_pool)
+        .execute(&mut *transaction)
         .await?;
 
-    tokio::fs::remove_file(image.get_uri(state)).await?;
+    transaction.commit().await?;
+
+    cleanup.remove_file(image.get_uri());
+    cleanup.remove_file(config::generate_proxy_image_uri(image.project_id, image.id));
+    cleanup.remove_folder(
+        config::generate_image_version_folder_uri(image.project_id).join(image.id.to_string()),
+    );
+    cleanup.remove_folder(
+        config::generate_preview_folder_uri(image.project_id).join(image.id.to_string()),
+    );
+    cleanup.run(state).await?;
 
     info!(
         id = ?image.id,
@@ -95,15 +175,15 @@ pub async fn get_image(project_id: Uuid, image_id: Uuid, state: &AppState) -> Re
     info!("Fetching image with ID: {}", image_id);
     let image = sqlx::query_as!(
         Image,
-        "SELECT id, name, project_id FROM images WHERE id = $1 AND project_id = $2",
+        "SELECT id, name, project_id, content_hash FROM images WHERE id = $1 AND project_id = $2",
         image_id,
         project_id
     )
     .fetch_one(&state.db_pool)
     .await?;
 
-    let path = image.get_uri(state);
-    let file = tokio::fs::read(&path).await?;
+    let path = image.get_uri();
+    let file = state.storage.read(&path).await?;
 
     info!(
         id = ?image.id,
@@ -1,3 +1,4 @@
 pub mod controller;
 pub mod model;
 pub mod router;
+pub mod upload;
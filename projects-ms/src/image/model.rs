@@ -1,4 +1,4 @@
-use crate::{config, AppState};
+use crate::config;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use uuid::Uuid;
@@ -12,10 +12,12 @@ pub struct Image {
     pub name: String,
     /// The project associated with the image.
     pub project_id: Uuid,
+    /// The sha256 of the image bytes, used to share the results of the tools between projects.
+    pub content_hash: Option<String>,
 }
 
 impl Image {
-    pub fn get_uri(&self, state: &AppState) -> PathBuf {
-        config::generate_image_uri(self.project_id, self.id, &self.name, state)
+    pub fn get_uri(&self) -> PathBuf {
+        config::generate_image_uri(self.project_id, self.id, &self.name)
     }
 }
@@ -1,18 +1,18 @@
 use crate::error::AppError::Forbidden;
 use crate::error::{AppError, Result};
-use crate::image::controller;
 use crate::image::model::Image;
+use crate::image::{controller, upload};
+use crate::signed_url::DownloadAccess;
 use crate::user::AccessTokenClaims;
-use crate::{project, AppState};
+use crate::{project, signed_url, AppState};
 use axum::extract::{DefaultBodyLimit, Multipart, Path, State};
 use axum::http::{header, HeaderValue};
 use axum::response::IntoResponse;
 use axum::routing::{get, post};
 use axum::{debug_handler, Json, Router};
 use serde::{Deserialize, Serialize};
-use std::io::Cursor;
+use tracing::error;
 use uuid::Uuid;
-use zip::ZipArchive;
 
 pub fn router(state: AppState) -> Router {
     let images_router = Router::new()
@@ -40,6 +40,26 @@ async fn create_image(
     }
 
     let mut result = vec![];
+    if let Err(err) = receive_images(project_id, &mut multipart, &mut result, &state).await {
+        // an upload is taken whole or not at all, e.g. when an archive is rejected halfway
+        for image in result {
+            if let Err(e) = controller::delete_image(image.id, project_id, &state).await {
+                error!(image = ?image.id, "Failed to delete image of a rejected upload: {}", e);
+            }
+        }
+        return Err(err);
+    }
+    Ok(Json(result))
+}
+
+/// Creates an image for each image field of the upload, and for each image of each zip
+/// archive field, adding them to `images` as they are created.
+async fn receive_images(
+    project_id: Uuid,
+    multipart: &mut Multipart,
+    images: &mut Vec<Image>,
+    state: &AppState,
+) -> Result<()> {
     while let Some(field) = multipart.next_field().await? {
         let file_name = field
             .file_name()
@@ -50,72 +70,29 @@ async fn create_image(
             .ok_or(AppError::MultipartMissing("Content-Type"))?
             .to_string();
 
-        let data = field.bytes().await?;
-
-        if content_type.starts_with("image/") {
-            let image = controller::create_image(project_id, file_name, data, &state).await?;
-            result.push(image);
-        } else if content_type == "application/zip" || file_name.to_lowercase().ends_with(".zip") {
-            let state_clone = state.clone();
-            let project_id_clone = project_id;
-            let zip_data = data.to_vec();
-
-            let extracted_images =
-                tokio::task::spawn_blocking(move || -> Result<Vec<(String, Vec<u8>)>> {
-                    let cursor = Cursor::new(zip_data);
-                    let mut zip = ZipArchive::new(cursor).map_err(|_| AppError::InvalidZip)?;
-
-                    let mut images = Vec::new();
-
-                    for i in 0..zip.len() {
-                        let mut file = zip.by_index(i).map_err(|_| AppError::InvalidZip)?;
-
-                        if file.is_dir() {
-                            continue;
-                        }
-
-                        let extracted_file_name = file.name().to_string();
-
-                        if is_image_file(&extracted_file_name) {
-                            let mut buffer = Vec::with_capacity(file.size() as usize);
-                            use std::io::Read;
-                            file.read_to_end(&mut buffer)?;
-
-                            images.push((extracted_file_name, buffer));
-                        }
-                    }
-
-                    Ok(images)
-                })
-                .await
-                .map_err(|_| AppError::InternalError)??;
-
-            for (extracted_file_name, buffer) in extracted_images {
-                let image = controller::create_image(
-                    project_id_clone,
-                    extracted_file_name,
-                    buffer.into(),
-                    &state_clone,
-                )
-                .await?;
-                result.push(image);
-            }
-        } else {
+        let is_image = content_type.starts_with("image/");
+        let is_zip =
+            content_type == "application/zip" || file_name.to_lowercase().ends_with(".zip");
+        if !is_image && !is_zip {
             return Err(AppError::NotAnImage(content_type));
         }
-    }
-    Ok(Json(result))
-}
 
-fn is_image_file(file_name: &str) -> bool {
-    let lower = file_name.to_lowercase();
-    lower.ends_with(".png")
-        || lower.ends_with(".jpg")
-        || lower.ends_with(".jpeg")
-        || lower.ends_with(".gif")
-        || lower.ends_with(".bmp")
-        || lower.ends_with(".tiff")
-        || lower.ends_with(".webp")
+        let upload = upload::receive_field(field, state).await?;
+
+        if is_image {
+            let image = controller::create_image(project_id, file_name, upload, state).await?;
+            images.push(image);
+        } else {
+            let mut extracted_images = upload::extract_zip_images(upload, state);
+            while let Some(extracted_image) = extracted_images.recv().await {
+                let (extracted_file_name, image) = extracted_image?;
+                let image =
+                    controller::create_image(project_id, extracted_file_name, image, state).await?;
+                images.push(image);
+            }
+        }
+    }
+    Ok(())
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -143,6 +120,7 @@ async fn get_images(
                 "{}/api/v1/projects/{}/images/{}",
                 state.config.picturas_public_url, project_id, image.id
             );
+            let url = signed_url::sign_url(url, project_id, image.id, &state);
             ImageWithUrl { image, url }
         })
         .collect::<Vec<_>>();
@@ -153,10 +131,10 @@ async fn get_images(
 #[debug_handler]
 async fn download_image(
     Path((project_id, image_id)): Path<(Uuid, Uuid)>,
-    user: AccessTokenClaims,
+    access: DownloadAccess,
     State(state): State<AppState>,
 ) -> Result<impl IntoResponse> {
-    if !project::controller::can_modify(project_id, user.sub, &state).await? {
+    if !access.can_download(project_id, image_id, &state).await? {
         return Err(Forbidden);
     }
 
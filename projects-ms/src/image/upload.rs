@@ -0,0 +1,150 @@
+use crate::config::Config;
+use crate::error::{AppError, Result};
+use crate::AppState;
+use axum::extract::multipart::Field;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use tempfile::NamedTempFile;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use zip::ZipArchive;
+
+/// A file received in a temporary file, removed once dropped.
+pub struct UploadedFile {
+    file: NamedTempFile,
+    pub content_hash: String,
+}
+
+impl UploadedFile {
+    pub fn path(&self) -> &Path {
+        self.file.path()
+    }
+}
+
+/// Writes a multipart field to a temporary file as it arrives, hashing it on the way, so the
+/// upload is never whole in memory.
+pub async fn receive_field(mut field: Field<'_>, state: &AppState) -> Result<UploadedFile> {
+    let file = NamedTempFile::new_in(&state.config.picturas_upload_folder)?;
+    let mut writer = tokio::io::BufWriter::new(tokio::fs::File::from_std(file.reopen()?));
+    let mut hasher = Sha256::new();
+
+    while let Some(chunk) = field.chunk().await? {
+        hasher.update(&chunk);
+        writer.write_all(&chunk).await?;
+    }
+    writer.flush().await?;
+
+    Ok(UploadedFile {
+        file,
+        content_hash: format!("{:x}", hasher.finalize()),
+    })
+}
+
+/// Extracts the images of a zip archive one entry at a time, each one sent to the returned
+/// channel once written to a temporary file, the next one only extracted after it is taken.
+/// Archives with too many entries, entries too large or compressed too much are rejected,
+/// guarding against zip bombs.
+pub fn extract_zip_images(
+    archive: UploadedFile,
+    state: &AppState,
+) -> mpsc::Receiver<Result<(String, UploadedFile)>> {
+    let (sender, receiver) = mpsc::channel(1);
+    let config = state.config.clone();
+
+    tokio::task::spawn_blocking(move || {
+        let result = (|| {
+            let mut zip =
+                ZipArchive::new(File::open(archive.path())?).map_err(|_| AppError::InvalidZip)?;
+
+            if zip.len() > config.picturas_zip_max_entries {
+                return Err(AppError::UnsafeZip(format!(
+                    "more than {} entries",
+                    config.picturas_zip_max_entries
+                )));
+            }
+
+            for i in 0..zip.len() {
+                let entry = zip.by_index(i).map_err(|_| AppError::InvalidZip)?;
+                if entry.is_dir() || !is_image_file(entry.name()) {
+                    continue;
+                }
+
+                let name = entry.name().to_string();
+                let image = extract_entry(entry, &config)?;
+                if sender.blocking_send(Ok((name, image))).is_err() {
+                    // the upload was aborted
+                    return Ok(());
+                }
+            }
+
+            Ok(())
+        })();
+
+        if let Err(err) = result {
+            let _ = sender.blocking_send(Err(err));
+        }
+    });
+
+    receiver
+}
+
+fn extract_entry(mut entry: zip::read::ZipFile<'_, File>, config: &Config) -> Result<UploadedFile> {
+    let max_size = config.picturas_zip_max_entry_size;
+    // the sizes in the archive can't be trusted, so they are checked on the extracted bytes too
+    if entry.size() > max_size {
+        return Err(AppError::UnsafeZip(format!(
+            "{} is larger than {max_size} bytes",
+            entry.name()
+        )));
+    }
+
+    let file = NamedTempFile::new_in(&config.picturas_upload_folder)?;
+    let mut writer = std::io::BufWriter::new(file.reopen()?);
+    let mut hasher = Sha256::new();
+    let mut size = 0u64;
+    let mut buffer = [0u8; 64 * 1024];
+
+    loop {
+        let read = entry.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+
+        size += read as u64;
+        if size > max_size {
+            return Err(AppError::UnsafeZip(format!(
+                "{} is larger than {max_size} bytes",
+                entry.name()
+            )));
+        }
+        if size / entry.compressed_size().max(1) > config.picturas_zip_max_compression_ratio {
+            return Err(AppError::UnsafeZip(format!(
+                "{} is compressed more than {}:1",
+                entry.name(),
+                config.picturas_zip_max_compression_ratio
+            )));
+        }
+
+        hasher.update(&buffer[..read]);
+        writer.write_all(&buffer[..read])?;
+    }
+    writer.flush()?;
+
+    Ok(UploadedFile {
+        file,
+        content_hash: format!("{:x}", hasher.finalize()),
+    })
+}
+
+fn is_image_file(file_name: &str) -> bool {
+    let lower = file_name.to_lowercase();
+    lower.ends_with(".png")
+        || lower.ends_with(".jpg")
+        || lower.ends_with(".jpeg")
+        || lower.ends_with(".gif")
+        || lower.ends_with(".bmp")
+        || lower.ends_with(".tiff")
+        || lower.ends_with(".webp")
+}
@@ -0,0 +1,554 @@
+use crate::error::Result;
+use crate::job::model::{Job, JobStep, JobStepStatus, JobSummary};
+use crate::tool::amqp::message::{ErrorObject, Metadata};
+use crate::tool::{queue, websocket};
+use crate::AppState;
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use sqlx::PgConnection;
+use std::collections::HashMap;
+use tracing::{error, info};
+use uuid::Uuid;
+
+/// Creates a job as the newest generation of its project, superseding the unfinished steps the
+/// previous generations of the same kind (preview or not) run on the images of the job.
+/// The generation of `job` is updated with the assigned one.
+pub async fn create_job(
+    job: &mut Job,
+    steps: &[JobStep],
+    transaction: &mut PgConnection,
+) -> Result<()> {
+    info!(id = ?job.id, steps = steps.len(), "Creating job for project: {}", job.project_id);
+
+    // serializes the creation of jobs of the same project
+    sqlx::query!(
+        "SELECT id FROM projects WHERE id = $1 FOR UPDATE",
+        job.project_id
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+
+    job.generation = sqlx::query_scalar!(
+        r#"INSERT INTO jobs (id, project_id, user_id, created_at, generation, priority, preview)
+           VALUES ($1, $2, $3, $4, (SELECT COALESCE(MAX(generation), 0) + 1 FROM jobs WHERE project_id = $2), $5, $6)
+           RETURNING generation"#,
+        job.id,
+        job.project_id,
+        job.user_id,
+        job.created_at,
+        job.priority,
+        job.preview
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+
+    let mut image_ids: Vec<Uuid> = steps.iter().map(|step| step.original_image_id).collect();
+    image_ids.sort_unstable();
+    image_ids.dedup();
+
+    sqlx::query!(
+        "UPDATE job_steps SET status = 'superseded', finished_at = $3 FROM jobs WHERE job_steps.job_id = jobs.id AND jobs.project_id = $1 AND jobs.generation < $2 AND jobs.preview = $4 AND job_steps.original_image_id = ANY($5) AND job_steps.status IN ('queued', 'running')",
+        job.project_id,
+        job.generation,
+        Utc::now(),
+        job.preview,
+        &image_ids
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    for step in steps {
+        sqlx::query!(
+            "INSERT INTO job_steps (id, job_id, original_image_id, tool_id, position, procedure, parameters, condition, status, message_id, parent_step_id, input_version_id, cache_key) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)",
+            step.id,
+            step.job_id,
+            step.original_image_id,
+            step.tool_id,
+            step.position,
+            step.procedure,
+            step.parameters,
+            step.condition,
+            step.status as JobStepStatus,
+            step.message_id,
+            step.parent_step_id,
+            step.input_version_id,
+            step.cache_key
+        )
+            .execute(&mut *transaction)
+            .await?;
+    }
+
+    Ok(())
+}
+
+pub async fn get_job(job_id: Uuid, state: &AppState) -> Result<Option<Job>> {
+    let job = sqlx::query_as!(
+        Job,
+        "SELECT id, project_id, user_id, created_at, cancelled_at, generation, priority, preview FROM jobs WHERE id = $1",
+        job_id
+    )
+    .fetch_optional(&state.db_pool)
+    .await?;
+
+    Ok(job)
+}
+
+pub async fn get_jobs(project_id: Uuid, state: &AppState) -> Result<Vec<JobSummary>> {
+    let jobs = sqlx::query!(
+        r#"SELECT j.id, j.project_id, j.user_id, j.created_at, j.cancelled_at, j.generation, j.priority, j.preview,
+                  COUNT(s.id) FILTER (WHERE s.status = 'queued')    AS "queued!",
+                  COUNT(s.id) FILTER (WHERE s.status = 'running')   AS "running!",
+                  COUNT(s.id) FILTER (WHERE s.status = 'succeeded') AS "succeeded!",
+                  COUNT(s.id) FILTER (WHERE s.status = 'failed')    AS "failed!",
+                  COUNT(s.id) FILTER (WHERE s.status = 'cancelled') AS "cancelled!",
+                  COUNT(s.id) FILTER (WHERE s.status = 'superseded') AS "superseded!",
+                  COUNT(s.id) FILTER (WHERE s.status = 'timed_out') AS "timed_out!"
+           FROM jobs j
+                    LEFT JOIN job_steps s ON s.job_id = j.id
+           WHERE j.project_id = $1
+           GROUP BY j.id
+           ORDER BY j.created_at DESC"#,
+        project_id
+    )
+    .fetch_all(&state.db_pool)
+    .await?
+    .into_iter()
+    .map(|row| JobSummary {
+        job: Job {
+            id: row.id,
+            project_id: row.project_id,
+            user_id: row.user_id,
+            created_at: row.created_at,
+            cancelled_at: row.cancelled_at,
+            generation: row.generation,
+            priority: row.priority,
+            preview: row.preview,
+        },
+        queued: row.queued,
+        running: row.running,
+        succeeded: row.succeeded,
+        failed: row.failed,
+        cancelled: row.cancelled,
+        superseded: row.superseded,
+        timed_out: row.timed_out,
+    })
+    .collect();
+
+    Ok(jobs)
+}
+
+/// Returns every step of a job, grouped by image and in the order they are applied.
+pub async fn get_job_steps(job_id: Uuid, state: &AppState) -> Result<Vec<JobStep>> {
+    let steps = sqlx::query_as!(
+        JobStep,
+        r#"SELECT id, job_id, original_image_id, tool_id, position, procedure, parameters, condition, status AS "status: JobStepStatus", message_id, started_at, finished_at, processing_time, error_code, error_message, attempts, skipped, parent_step_id, input_version_id, cache_key FROM job_steps WHERE job_id = $1 ORDER BY original_image_id, position ASC"#,
+        job_id
+    )
+        .fetch_all(&state.db_pool)
+        .await?;
+
+    Ok(steps)
+}
+
+pub async fn get_step_by_message_id(message_id: Uuid, state: &AppState) -> Result<Option<JobStep>> {
+    let step = sqlx::query_as!(
+        JobStep,
+        r#"SELECT id, job_id, original_image_id, tool_id, position, procedure, parameters, condition, status AS "status: JobStepStatus", message_id, started_at, finished_at, processing_time, error_code, error_message, attempts, skipped, parent_step_id, input_version_id, cache_key FROM job_steps WHERE message_id = $1"#,
+        message_id
+    )
+        .fetch_optional(&state.db_pool)
+        .await?;
+
+    Ok(step)
+}
+
+pub async fn get_step(step_id: Uuid, state: &AppState) -> Result<Option<JobStep>> {
+    let step = sqlx::query_as!(
+        JobStep,
+        r#"SELECT id, job_id, original_image_id, tool_id, position, procedure, parameters, condition, status AS "status: JobStepStatus", message_id, started_at, finished_at, processing_time, error_code, error_message, attempts, skipped, parent_step_id, input_version_id, cache_key FROM job_steps WHERE id = $1"#,
+        step_id
+    )
+        .fetch_optional(&state.db_pool)
+        .await?;
+
+    Ok(step)
+}
+
+/// Returns the steps that depend on the output of a step, up to the given depth, the deepest
+/// steps first.
+pub async fn get_descendant_steps(
+    step_id: Uuid,
+    depth: i32,
+    state: &AppState,
+) -> Result<Vec<JobStep>> {
+    let steps = sqlx::query_as!(
+        JobStep,
+        r#"WITH RECURSIVE descendants AS (SELECT id, 1 AS depth FROM job_steps WHERE parent_step_id = $1
+                                         UNION ALL
+                                         SELECT s.id, d.depth + 1 FROM job_steps s JOIN descendants d ON s.parent_step_id = d.id WHERE d.depth < $2)
+           SELECT s.id, s.job_id, s.original_image_id, s.tool_id, s.position, s.procedure, s.parameters, s.condition, s.status AS "status: JobStepStatus", s.message_id, s.started_at, s.finished_at, s.processing_time, s.error_code, s.error_message, s.attempts, s.skipped, s.parent_step_id, s.input_version_id, s.cache_key
+           FROM job_steps s
+                    JOIN descendants d ON d.id = s.id
+           ORDER BY d.depth DESC"#,
+        step_id,
+        depth
+    )
+        .fetch_all(&state.db_pool)
+        .await?;
+
+    Ok(steps)
+}
+
+/// Returns the steps that can be published along with their jobs, i.e. queued steps that take the
+/// original image or an image version as input or whose parent step already succeeded, ordered by
+/// priority and then by the age of their job.
+/// Only the first steps of each user that fit under `max_running` with the steps the user already
+/// has waiting for a tool are returned.
+pub async fn get_ready_steps(max_running: i64, state: &AppState) -> Result<Vec<(Job, JobStep)>> {
+    let steps = sqlx::query!(
+        r#"SELECT s.id, s.job_id, s.original_image_id, s.tool_id, s.position, s.procedure, s.parameters, s.condition, s.status AS "status: JobStepStatus", s.message_id, s.started_at, s.finished_at, s.processing_time, s.error_code, s.error_message, s.attempts, s.skipped, s.parent_step_id, s.input_version_id, s.cache_key,
+                  j.project_id, j.user_id, j.created_at, j.cancelled_at, j.generation, j.priority, j.preview
+           FROM job_steps s
+                    JOIN jobs j ON j.id = s.job_id
+           WHERE s.id IN (SELECT ready.id
+                          FROM (SELECT s.id,
+                                       j.user_id,
+                                       ROW_NUMBER() OVER (PARTITION BY j.user_id
+                                           ORDER BY j.priority DESC, j.created_at, s.original_image_id) AS rank
+                                FROM job_steps s
+                                         JOIN jobs j ON j.id = s.job_id
+                                WHERE s.status = 'queued'
+                                  AND (s.available_at IS NULL OR s.available_at <= $1)
+                                  AND (s.parent_step_id IS NULL OR EXISTS (SELECT 1
+                                                                           FROM job_steps p
+                                                                           WHERE p.id = s.parent_step_id
+                                                                             AND p.status = 'succeeded'))) ready
+                                   LEFT JOIN (SELECT j.user_id, COUNT(*) AS running
+                                              FROM job_steps s
+                                                       JOIN jobs j ON j.id = s.job_id
+                                              WHERE s.status = 'running'
+                                              GROUP BY j.user_id) running ON running.user_id = ready.user_id
+                          WHERE ready.rank <= $2 - COALESCE(running.running, 0))
+           ORDER BY j.priority DESC, j.created_at, s.original_image_id"#,
+        Utc::now(),
+        max_running
+    )
+    .fetch_all(&state.db_pool)
+    .await?
+    .into_iter()
+    .map(|row| {
+        let job = Job {
+            id: row.job_id,
+            project_id: row.project_id,
+            user_id: row.user_id,
+            created_at: row.created_at,
+            cancelled_at: row.cancelled_at,
+            generation: row.generation,
+            priority: row.priority,
+            preview: row.preview,
+        };
+        let step = JobStep {
+            id: row.id,
+            job_id: row.job_id,
+            original_image_id: row.original_image_id,
+            tool_id: row.tool_id,
+            position: row.position,
+            procedure: row.procedure,
+            parameters: row.parameters,
+            condition: row.condition,
+            status: row.status,
+            message_id: row.message_id,
+            started_at: row.started_at,
+            finished_at: row.finished_at,
+            processing_time: row.processing_time,
+            error_code: row.error_code,
+            error_message: row.error_message,
+            attempts: row.attempts,
+            skipped: row.skipped,
+            parent_step_id: row.parent_step_id,
+            input_version_id: row.input_version_id,
+            cache_key: row.cache_key,
+        };
+        (job, step)
+    })
+    .collect();
+
+    Ok(steps)
+}
+
+/// Returns the amount of steps each user has waiting for a tool.
+pub async fn get_running_steps_per_user(state: &AppState) -> Result<HashMap<Uuid, i64>> {
+    let running = sqlx::query!(
+        r#"SELECT j.user_id, COUNT(*) AS "running!"
+           FROM job_steps s
+                    JOIN jobs j ON j.id = s.job_id
+           WHERE s.status = 'running'
+           GROUP BY j.user_id"#
+    )
+    .fetch_all(&state.db_pool)
+    .await?
+    .into_iter()
+    .map(|row| (row.user_id, row.running))
+    .collect();
+
+    Ok(running)
+}
+
+/// Returns the running steps that didn't get a result before their deadline.
+pub async fn get_timed_out_steps(state: &AppState) -> Result<Vec<JobStep>> {
+    let steps = sqlx::query_as!(
+        JobStep,
+        r#"SELECT id, job_id, original_image_id, tool_id, position, procedure, parameters, condition, status AS "status: JobStepStatus", message_id, started_at, finished_at, processing_time, error_code, error_message, attempts, skipped, parent_step_id, input_version_id, cache_key FROM job_steps WHERE status = 'running' AND deadline < $1"#,
+        Utc::now()
+    )
+    .fetch_all(&state.db_pool)
+    .await?;
+
+    Ok(steps)
+}
+
+/// Marks a queued step as running, it times out if no result arrives before `deadline`.
+/// Returns false if the step wasn't queued anymore (e.g. the job was cancelled).
+pub async fn mark_step_running(
+    step_id: Uuid,
+    message_id: Uuid,
+    deadline: DateTime<Utc>,
+    state: &AppState,
+) -> Result<bool> {
+    let result = sqlx::query!(
+        "UPDATE job_steps SET status = 'running', message_id = $2, started_at = $3, deadline = $4 WHERE id = $1 AND status = 'queued'",
+        step_id,
+        message_id,
+        Utc::now(),
+        deadline
+    )
+    .execute(&state.db_pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Puts a step whose request couldn't be published back in the queue, so it is published again
+/// instead of waiting for its deadline.
+pub async fn mark_step_unpublished(
+    step_id: Uuid,
+    message_id: Uuid,
+    state: &AppState,
+) -> Result<()> {
+    sqlx::query!(
+        "UPDATE job_steps SET status = 'queued', message_id = NULL, started_at = NULL, deadline = NULL WHERE id = $1 AND message_id = $2 AND status = 'running'",
+        step_id,
+        message_id
+    )
+    .execute(&state.db_pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Marks a queued step as succeeded without running its tool, as its condition didn't match.
+/// Returns false if the step wasn't queued anymore (e.g. the job was cancelled).
+pub async fn mark_step_skipped(step_id: Uuid, state: &AppState) -> Result<bool> {
+    let now = Utc::now();
+    let result = sqlx::query!(
+        "UPDATE job_steps SET status = 'succeeded', skipped = TRUE, started_at = $2, finished_at = $2, processing_time = 0 WHERE id = $1 AND status = 'queued'",
+        step_id,
+        now
+    )
+    .execute(&state.db_pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Marks a queued step as succeeded with a result that was already cached.
+/// Returns false if the step wasn't queued anymore (e.g. the job was cancelled).
+pub async fn mark_step_cached(step_id: Uuid, transaction: &mut PgConnection) -> Result<bool> {
+    let now = Utc::now();
+    let result = sqlx::query!(
+        "UPDATE job_steps SET status = 'succeeded', started_at = $2, finished_at = $2, processing_time = 0 WHERE id = $1 AND status = 'queued'",
+        step_id,
+        now
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Marks a running step as succeeded.
+/// Returns false if the step wasn't running anymore (e.g. the job was cancelled).
+pub async fn mark_step_succeeded(
+    step_id: Uuid,
+    metadata: &Metadata,
+    transaction: &mut PgConnection,
+) -> Result<bool> {
+    let result = sqlx::query!(
+        "UPDATE job_steps SET status = 'succeeded', finished_at = $2, processing_time = $3 WHERE id = $1 AND status = 'running'",
+        step_id,
+        Utc::now(),
+        metadata.processing_time
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Puts a failed running step back in the queue to be retried once `available_at` is reached,
+/// keeping the error it failed with. The message id is cleared, so a late result of the failed
+/// attempt is ignored instead of being taken for the result of the next one.
+/// Returns false if the step wasn't running anymore (e.g. the job was cancelled).
+pub async fn mark_step_retrying(
+    step: &JobStep,
+    error: &ErrorObject,
+    processing_time: Option<f64>,
+    available_at: DateTime<Utc>,
+    state: &AppState,
+) -> Result<bool> {
+    let result = sqlx::query!(
+        "UPDATE job_steps SET status = 'queued', message_id = NULL, started_at = NULL, deadline = NULL, attempts = attempts + 1, processing_time = $2, error_code = $3, error_message = $4, available_at = $5 WHERE id = $1 AND status = 'running'",
+        step.id,
+        processing_time,
+        error.code,
+        error.message,
+        available_at
+    )
+    .execute(&state.db_pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Marks a running step as failed (or timed out), along with the steps that depend on its output.
+/// Returns false if the step wasn't running anymore (e.g. the job was cancelled).
+pub async fn mark_step_failed(
+    step: &JobStep,
+    status: JobStepStatus,
+    error: &ErrorObject,
+    processing_time: Option<f64>,
+    state: &AppState,
+) -> Result<bool> {
+    let mut transaction = state.db_pool.begin().await?;
+
+    let result = sqlx::query!(
+        "UPDATE job_steps SET status = $2, finished_at = $3, processing_time = $4, error_code = $5, error_message = $6 WHERE id = $1 AND status = 'running'",
+        step.id,
+        status as JobStepStatus,
+        Utc::now(),
+        processing_time,
+        error.code,
+        error.message
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    // the steps that depend on the failed one can't run anymore, the other branches still can
+    sqlx::query!(
+        r#"WITH RECURSIVE descendants AS (SELECT id FROM job_steps WHERE parent_step_id = $1
+                                         UNION
+                                         SELECT s.id FROM job_steps s JOIN descendants d ON s.parent_step_id = d.id)
+           UPDATE job_steps SET status = 'failed' WHERE id IN (SELECT id FROM descendants) AND status = 'queued'"#,
+        step.id
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(true)
+}
+
+/// Cancels every step of the job that didn't finish yet.
+/// Results of steps that were already sent to the tools will be discarded once they arrive.
+pub async fn cancel_job(job: &Job, state: &AppState) -> Result<()> {
+    info!("Cancelling job with ID: {}", job.id);
+    let now = Utc::now();
+    let mut transaction = state.db_pool.begin().await?;
+
+    sqlx::query!(
+        "UPDATE jobs SET cancelled_at = $2 WHERE id = $1 AND cancelled_at IS NULL",
+        job.id,
+        now
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    sqlx::query!(
+        "UPDATE job_steps SET status = 'cancelled', finished_at = $2 WHERE job_id = $1 AND status IN ('queued', 'running')",
+        job.id,
+        now
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    // the cancelled steps no longer count for the in-flight limit of the user
+    queue::schedule(state);
+
+    if let Err(err) = websocket::send_ws_message(
+        state,
+        job.project_id,
+        job.user_id,
+        json!({
+            "job_cancelled": job.id,
+        }),
+    )
+    .await
+    {
+        error!("Failed to send message to websocket: {}", err);
+    }
+
+    Ok(())
+}
+
+/// Returns the generation of the latest job of a project of the given kind that runs on an
+/// image, if any.
+pub async fn get_current_generation(
+    project_id: Uuid,
+    original_image_id: Uuid,
+    preview: bool,
+    state: &AppState,
+) -> Result<Option<i32>> {
+    let generation = sqlx::query_scalar!(
+        "SELECT MAX(jobs.generation) FROM jobs JOIN job_steps ON job_steps.job_id = jobs.id WHERE jobs.project_id = $1 AND jobs.preview = $2 AND job_steps.original_image_id = $3",
+        project_id,
+        preview,
+        original_image_id
+    )
+    .fetch_one(&state.db_pool)
+    .await?;
+
+    Ok(generation)
+}
+
+/// Supersedes the unfinished steps of every job of a project, e.g. when its tools are replaced.
+pub async fn supersede_jobs(project_id: Uuid, transaction: &mut PgConnection) -> Result<()> {
+    sqlx::query!(
+        "UPDATE job_steps SET status = 'superseded', finished_at = $2 FROM jobs WHERE job_steps.job_id = jobs.id AND jobs.project_id = $1 AND job_steps.status IN ('queued', 'running')",
+        project_id,
+        Utc::now()
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// Marks a running step of a superseded generation as superseded.
+pub async fn mark_step_superseded(step_id: Uuid, state: &AppState) -> Result<()> {
+    sqlx::query!(
+        "UPDATE job_steps SET status = 'superseded', finished_at = $2 WHERE id = $1 AND status IN ('queued', 'running')",
+        step_id,
+        Utc::now()
+    )
+    .execute(&state.db_pool)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,237 @@
+use crate::config;
+use crate::tool::amqp::message::ErrorObject;
+use crate::tool::model::RequestedTool;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::types::JsonValue;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+/// A run of the project tools over a set of images.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Job {
+    /// The unique identifier of the job.
+    pub id: Uuid,
+    /// The project associated with the job.
+    pub project_id: Uuid,
+    /// The user that started the job.
+    pub user_id: Uuid,
+    /// The date and time the job was created.
+    pub created_at: DateTime<Utc>,
+    /// The date and time the job was cancelled, if it was.
+    pub cancelled_at: Option<DateTime<Utc>>,
+    /// The run number of the job in the project, results of older generations are rejected.
+    pub generation: i32,
+    /// The priority the steps of the job are published to the tools with.
+    pub priority: i16,
+    /// Whether the job runs on the proxy images, its results are kept apart from the image versions.
+    pub preview: bool,
+}
+
+/// The state of a single step of a job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "job_step_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum JobStepStatus {
+    /// Waiting for the parent step to finish.
+    Queued,
+    /// Published to the tool, waiting for the result.
+    Running,
+    Succeeded,
+    Failed,
+    /// The job was cancelled before the step finished.
+    Cancelled,
+    /// A newer job of the project was created before the step finished.
+    Superseded,
+    /// The tool didn't answer before the deadline of the step.
+    TimedOut,
+}
+
+/// A tool to be applied to an image as part of a job.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobStep {
+    /// The unique identifier of the step, also used for the image version it creates.
+    pub id: Uuid,
+    /// The job associated with the step.
+    pub job_id: Uuid,
+    /// The image the chain of steps is applied to.
+    pub original_image_id: Uuid,
+    /// The tool this step applies.
+    pub tool_id: Uuid,
+    /// The position of the tool in the project when the job was created.
+    pub position: i32,
+    /// The procedure to be applied to the image.
+    pub procedure: String,
+    /// The parameters of the procedure.
+    pub parameters: JsonValue,
+    /// The condition the input image must match for the tool to run, if any.
+    pub condition: Option<JsonValue>,
+    /// The current state of the step.
+    pub status: JobStepStatus,
+    /// The id of the request message sent to the tool, if already published.
+    pub message_id: Option<Uuid>,
+    /// The date and time the step was published to the tool.
+    pub started_at: Option<DateTime<Utc>>,
+    /// The date and time the result of the step was received.
+    pub finished_at: Option<DateTime<Utc>>,
+    /// The time in seconds the tool took to process the step.
+    pub processing_time: Option<f64>,
+    /// The error code returned by the tool if the step failed.
+    pub error_code: Option<String>,
+    /// The error message returned by the tool if the step failed.
+    pub error_message: Option<String>,
+    /// The amount of times the step was retried after failing.
+    pub attempts: i32,
+    /// Whether the step succeeded without running the tool, as its condition didn't match.
+    pub skipped: bool,
+    /// The step whose output is the input of this step, none if the step takes the original image
+    /// or an image version as input.
+    pub parent_step_id: Option<Uuid>,
+    /// The image version the step takes as input when the steps before it were reused from a
+    /// previous job, otherwise the output of the parent step or the original image is used.
+    pub input_version_id: Option<Uuid>,
+    /// The key of the result of the step in the result cache, if the original image has a known hash.
+    pub cache_key: Option<String>,
+}
+
+/// A job with the amount of steps in each state.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JobSummary {
+    #[serde(flatten)]
+    pub job: Job,
+    pub queued: i64,
+    pub running: i64,
+    pub succeeded: i64,
+    pub failed: i64,
+    pub cancelled: i64,
+    pub superseded: i64,
+    pub timed_out: i64,
+}
+
+/// The state of every step of a job, grouped by image.
+#[derive(Debug, Serialize)]
+pub struct JobReport {
+    #[serde(flatten)]
+    pub summary: JobSummary,
+    pub images: Vec<ImageJobReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImageJobReport {
+    pub original_image_id: Uuid,
+    pub steps: Vec<JobStepReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobStepReport {
+    pub id: Uuid,
+    pub tool_id: Uuid,
+    pub position: i32,
+    pub procedure: String,
+    pub status: JobStepStatus,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub processing_time: Option<f64>,
+    pub attempts: i32,
+    pub error: Option<ErrorObject>,
+}
+
+impl JobReport {
+    /// Builds the report of a job, `steps` must be sorted by image.
+    pub fn from_steps(job: Job, steps: Vec<JobStep>) -> Self {
+        let mut summary = JobSummary {
+            job,
+            queued: 0,
+            running: 0,
+            succeeded: 0,
+            failed: 0,
+            cancelled: 0,
+            superseded: 0,
+            timed_out: 0,
+        };
+        let mut images: Vec<ImageJobReport> = vec![];
+
+        for step in steps {
+            match step.status {
+                JobStepStatus::Queued => summary.queued += 1,
+                JobStepStatus::Running => summary.running += 1,
+                JobStepStatus::Succeeded => summary.succeeded += 1,
+                JobStepStatus::Failed => summary.failed += 1,
+                JobStepStatus::Cancelled => summary.cancelled += 1,
+                JobStepStatus::Superseded => summary.superseded += 1,
+                JobStepStatus::TimedOut => summary.timed_out += 1,
+            }
+
+            let image = match images.last_mut() {
+                Some(image) if image.original_image_id == step.original_image_id => image,
+                _ => {
+                    images.push(ImageJobReport {
+                        original_image_id: step.original_image_id,
+                        steps: vec![],
+                    });
+                    images.last_mut().unwrap()
+                }
+            };
+
+            image.steps.push(step.into());
+        }
+
+        Self { summary, images }
+    }
+}
+
+impl From<JobStep> for JobStepReport {
+    fn from(step: JobStep) -> Self {
+        let error = step.error_code.map(|code| ErrorObject {
+            code,
+            message: step.error_message.unwrap_or_default(),
+        });
+
+        Self {
+            id: step.id,
+            tool_id: step.tool_id,
+            position: step.position,
+            procedure: step.procedure,
+            status: step.status,
+            started_at: step.started_at,
+            finished_at: step.finished_at,
+            processing_time: step.processing_time,
+            attempts: step.attempts,
+            error,
+        }
+    }
+}
+
+impl JobStep {
+    pub fn get_output_uri(&self, job: &Job) -> PathBuf {
+        if job.preview {
+            return config::generate_preview_output_uri(
+                job.project_id,
+                self.original_image_id,
+                self.id,
+            );
+        }
+
+        match &self.cache_key {
+            Some(cache_key) => config::generate_cached_result_uri(cache_key),
+            None => config::generate_image_version_output_uri(
+                job.project_id,
+                self.original_image_id,
+                self.id,
+            ),
+        }
+    }
+}
+
+impl TryInto<RequestedTool> for JobStep {
+    type Error = serde_json::Error;
+
+    fn try_into(self) -> Result<RequestedTool, Self::Error> {
+        Ok(RequestedTool {
+            procedure: self.procedure,
+            parameters: serde_json::from_value(self.parameters)?,
+            condition: self.condition.map(serde_json::from_value).transpose()?,
+            enabled: true,
+        })
+    }
+}
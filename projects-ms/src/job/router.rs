@@ -0,0 +1,78 @@
+use crate::error::AppError::Forbidden;
+use crate::error::{AppError, Result};
+use crate::job::controller;
+use crate::job::model::JobReport;
+use crate::user::AccessTokenClaims;
+use crate::{project, AppState};
+use axum::extract::{Path, State};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::{debug_handler, Json, Router};
+use uuid::Uuid;
+
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/projects/{project_id}/jobs", get(get_jobs))
+        .route(
+            "/projects/{project_id}/jobs/{job_id}",
+            get(get_job).delete(cancel_job),
+        )
+        .with_state(state)
+}
+
+#[debug_handler]
+async fn get_jobs(
+    Path(project_id): Path<Uuid>,
+    user: AccessTokenClaims,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse> {
+    if !project::controller::can_modify(project_id, user.sub, &state).await? {
+        return Err(Forbidden);
+    }
+
+    let jobs = controller::get_jobs(project_id, &state).await?;
+    Ok(Json(jobs))
+}
+
+#[debug_handler]
+async fn get_job(
+    Path((project_id, job_id)): Path<(Uuid, Uuid)>,
+    user: AccessTokenClaims,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse> {
+    if !project::controller::can_modify(project_id, user.sub, &state).await? {
+        return Err(Forbidden);
+    }
+
+    let job = controller::get_job(job_id, &state)
+        .await?
+        .filter(|job| job.project_id == project_id)
+        .ok_or(AppError::EntityNotFound)?;
+
+    let steps = controller::get_job_steps(job.id, &state).await?;
+    Ok(Json(JobReport::from_steps(job, steps)))
+}
+
+#[debug_handler]
+async fn cancel_job(
+    Path((project_id, job_id)): Path<(Uuid, Uuid)>,
+    user: AccessTokenClaims,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse> {
+    if !project::controller::can_modify(project_id, user.sub, &state).await? {
+        return Err(Forbidden);
+    }
+
+    let job = controller::get_job(job_id, &state)
+        .await?
+        .filter(|job| job.project_id == project_id)
+        .ok_or(AppError::EntityNotFound)?;
+
+    controller::cancel_job(&job, &state).await?;
+
+    let job = controller::get_job(job.id, &state)
+        .await?
+        .ok_or(AppError::EntityNotFound)?;
+    let steps = controller::get_job_steps(job.id, &state).await?;
+    Ok(Json(JobReport::from_steps(job, steps)))
+}
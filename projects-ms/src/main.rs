@@ -1,9 +1,14 @@
+mod cleanup;
 mod config;
 mod error;
 mod image;
+mod job;
+mod preset;
 mod project;
 mod router;
+mod signed_url;
 mod state;
+mod storage;
 mod tool;
 mod user;
 
@@ -44,22 +49,41 @@ async fn main() {
     let bind_address = (IpAddr::from_str(&config.bind_ip).unwrap(), config.bind_port);
     let listener = tokio::net::TcpListener::bind(bind_address).await.unwrap();
 
-    let rabbit_mq_controller = RabbitMqController::new(8, &config).await;
+    let rabbit_mq_controller = RabbitMqController::new(8, &config)
+        .await
+        .expect("Failed to set up RabbitMQ");
+
+    let storage = storage::from_config(&config).expect("Failed to configure the storage");
 
     let state = AppState {
         db_pool: pg_pool,
         config: Arc::new(config),
         rabbit_mq_controller: Arc::new(rabbit_mq_controller),
-        queued_tools: Default::default(),
         connected_ws_clients: Default::default(),
+        scheduler_notify: Default::default(),
+        live_previews: Default::default(),
+        tool_catalogue: Default::default(),
+        storage,
     };
 
-    let rabbit_mq_consumer = state.rabbit_mq_controller.create_consumer(&state).await;
+    let rabbit_mq_consumer = state
+        .rabbit_mq_controller
+        .create_consumer(&state)
+        .await
+        .expect("Failed to create the results consumer");
+    let rabbit_mq_control_consumer = state
+        .rabbit_mq_controller
+        .create_control_consumer(&state)
+        .await
+        .expect("Failed to create the control consumer");
 
     info!("Starting server at {}:{}", bind_address.0, bind_address.1);
 
     tokio::select! {
         _ = tool::queue::run_rabbit_mq_results_read_loop(rabbit_mq_consumer, state.clone()) => {}
+        _ = tool::catalogue::run_rabbit_mq_control_read_loop(rabbit_mq_control_consumer, state.clone()) => {}
+        _ = tool::queue::run_timeout_sweeper(state.clone()) => {}
+        _ = tool::queue::run_scheduler(state.clone()) => {}
         _ = axum::serve(listener, router::router(state).layer(TraceLayer::new_for_http())) => {}
     }
 }
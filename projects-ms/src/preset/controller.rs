@@ -0,0 +1,240 @@
+use crate::error::{AppError, Result};
+use crate::preset::model::{Preset, PresetVersion, PresetWithTools};
+use crate::tool::model::{PipelineTool, Tool};
+use crate::tool::validation;
+use crate::{tool, AppState};
+use chrono::Utc;
+use std::collections::HashMap;
+use tracing::info;
+use uuid::Uuid;
+
+pub async fn create_preset(
+    owner: Uuid,
+    name: String,
+    tools: Vec<PipelineTool>,
+    state: &AppState,
+) -> Result<PresetWithTools> {
+    info!("Creating preset with name: {}", name);
+    validate_pipeline_tools(&tools, state)?;
+
+    let now = Utc::now();
+    let preset = Preset {
+        id: Uuid::new_v4(),
+        user_id: owner,
+        name,
+        version: 1,
+        created_at: now,
+        updated_at: now,
+    };
+    let tools = serde_json::to_value(tools)?;
+
+    let mut transaction = state.db_pool.begin().await?;
+
+    sqlx::query!(
+        "INSERT INTO presets (id, user_id, name, version, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6)",
+        preset.id,
+        preset.user_id,
+        preset.name,
+        preset.version,
+        preset.created_at,
+        preset.updated_at
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    sqlx::query!(
+        "INSERT INTO preset_versions (preset_id, version, tools, created_at) VALUES ($1, $2, $3, $4)",
+        preset.id,
+        preset.version,
+        tools,
+        now
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    info!("Preset created with ID: {}", preset.id);
+    Ok(PresetWithTools { preset, tools })
+}
+
+pub async fn get_presets(user: Uuid, state: &AppState) -> Result<Vec<Preset>> {
+    let presets = sqlx::query_as!(
+        Preset,
+        "SELECT id, user_id, name, version, created_at, updated_at FROM presets WHERE user_id = $1 ORDER BY name",
+        user
+    )
+    .fetch_all(&state.db_pool)
+    .await?;
+
+    Ok(presets)
+}
+
+pub async fn get_preset(preset_id: Uuid, state: &AppState) -> Result<Preset> {
+    let preset = sqlx::query_as!(
+        Preset,
+        "SELECT id, user_id, name, version, created_at, updated_at FROM presets WHERE id = $1",
+        preset_id
+    )
+    .fetch_optional(&state.db_pool)
+    .await?
+    .ok_or(AppError::EntityNotFound)?;
+
+    Ok(preset)
+}
+
+pub async fn get_preset_versions(preset_id: Uuid, state: &AppState) -> Result<Vec<PresetVersion>> {
+    let versions = sqlx::query_as!(
+        PresetVersion,
+        "SELECT preset_id, version, tools, created_at FROM preset_versions WHERE preset_id = $1 ORDER BY version",
+        preset_id
+    )
+    .fetch_all(&state.db_pool)
+    .await?;
+
+    Ok(versions)
+}
+
+pub async fn get_preset_version(
+    preset_id: Uuid,
+    version: i32,
+    state: &AppState,
+) -> Result<PresetVersion> {
+    let version = sqlx::query_as!(
+        PresetVersion,
+        "SELECT preset_id, version, tools, created_at FROM preset_versions WHERE preset_id = $1 AND version = $2",
+        preset_id,
+        version
+    )
+    .fetch_optional(&state.db_pool)
+    .await?
+    .ok_or(AppError::EntityNotFound)?;
+
+    Ok(version)
+}
+
+/// Renames a preset and/or saves a new version of its tools, the previous versions are kept.
+pub async fn update_preset(
+    preset_id: Uuid,
+    name: Option<String>,
+    tools: Option<Vec<PipelineTool>>,
+    state: &AppState,
+) -> Result<PresetWithTools> {
+    info!("Updating preset with ID: {}", preset_id);
+    if let Some(tools) = &tools {
+        validate_pipeline_tools(tools, state)?;
+    }
+
+    let now = Utc::now();
+    let mut transaction = state.db_pool.begin().await?;
+
+    if let Some(name) = name {
+        sqlx::query!(
+            "UPDATE presets SET name = $2, updated_at = $3 WHERE id = $1",
+            preset_id,
+            name,
+            now
+        )
+        .execute(&mut *transaction)
+        .await?;
+    }
+
+    if let Some(tools) = tools {
+        // the row lock keeps concurrent updates from creating the same version
+        let version = sqlx::query_scalar!(
+            "UPDATE presets SET version = version + 1, updated_at = $2 WHERE id = $1 RETURNING version",
+            preset_id,
+            now
+        )
+        .fetch_one(&mut *transaction)
+        .await?;
+
+        sqlx::query!(
+            "INSERT INTO preset_versions (preset_id, version, tools, created_at) VALUES ($1, $2, $3, $4)",
+            preset_id,
+            version,
+            serde_json::to_value(tools)?,
+            now
+        )
+        .execute(&mut *transaction)
+        .await?;
+    }
+
+    transaction.commit().await?;
+
+    let preset = get_preset(preset_id, state).await?;
+    let version = get_preset_version(preset.id, preset.version, state).await?;
+
+    Ok(PresetWithTools {
+        preset,
+        tools: version.tools,
+    })
+}
+
+pub async fn delete_preset(preset_id: Uuid, state: &AppState) -> Result<()> {
+    info!("Deleting preset with ID: {}", preset_id);
+    sqlx::query!("DELETE FROM presets WHERE id = $1", preset_id)
+        .execute(&state.db_pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Sets the tools of a preset version to a project, replacing its tools or appending them after
+/// the last one, in which case the tools of the preset that take the original image take the
+/// output of the last tool instead.
+/// The overrides are merged over the parameters of the tools, by the index of the tool in the
+/// preset.
+pub async fn instantiate_preset(
+    project_uuid: Uuid,
+    version: &PresetVersion,
+    overrides: HashMap<usize, HashMap<String, serde_json::Value>>,
+    append: bool,
+    state: &AppState,
+) -> Result<Vec<Tool>> {
+    let mut tools = version.pipeline_tools()?;
+
+    for (index, parameters) in overrides {
+        let tool = tools.get_mut(index).ok_or(AppError::InvalidPipeline(
+            "an override refers to a tool that isn't in the preset",
+        ))?;
+        tool.tool.parameters.extend(parameters);
+    }
+
+    // the tools are checked before any is appended, so a preset is never instantiated halfway
+    validate_pipeline_tools(&tools, state)?;
+
+    info!(
+        preset = ?version.preset_id,
+        version = version.version,
+        append,
+        "Instantiating preset into project: {}", project_uuid
+    );
+
+    if !append {
+        let tools = tools
+            .into_iter()
+            .map(|tool| (tool.tool, tool.parent))
+            .collect();
+        return tool::controller::set_tools(project_uuid, tools, state).await;
+    }
+
+    tool::controller::append_tools(project_uuid, tools, state).await
+}
+
+/// Checks the tools of a preset, and that each one takes the output of a tool before it.
+fn validate_pipeline_tools(tools: &[PipelineTool], state: &AppState) -> Result<()> {
+    validation::validate_tools(tools.iter().map(|tool| &tool.tool), state)?;
+
+    let takes_later_output = tools
+        .iter()
+        .enumerate()
+        .any(|(index, tool)| tool.parent.is_some_and(|parent| parent >= index));
+    if takes_later_output {
+        return Err(AppError::InvalidPipeline(
+            "a tool can only take the output of a tool before it",
+        ));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use crate::tool::model::PipelineTool;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::types::JsonValue;
+use uuid::Uuid;
+
+/// A list of tools saved by a user, to be instantiated into any of their projects.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Preset {
+    /// The unique identifier of the preset.
+    pub id: Uuid,
+    /// The user that owns the preset.
+    pub user_id: Uuid,
+    /// The name of the preset.
+    pub name: String,
+    /// The latest version of the tools of the preset.
+    pub version: i32,
+    /// The date and time the preset was created.
+    pub created_at: DateTime<Utc>,
+    /// The date and time the preset was last updated.
+    pub updated_at: DateTime<Utc>,
+}
+
+/// The tools of a preset at some point in time.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PresetVersion {
+    /// The preset the version belongs to.
+    pub preset_id: Uuid,
+    /// The number of the version, starting at 1.
+    pub version: i32,
+    /// The tools of the preset, in the order they are applied, along with the index of the tool
+    /// each one takes the output of.
+    pub tools: JsonValue,
+    /// The date and time the version was created.
+    pub created_at: DateTime<Utc>,
+}
+
+impl PresetVersion {
+    pub fn pipeline_tools(&self) -> Result<Vec<PipelineTool>, serde_json::Error> {
+        serde_json::from_value(self.tools.clone())
+    }
+}
+
+/// A preset along with the tools of one of its versions.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PresetWithTools {
+    #[serde(flatten)]
+    pub preset: Preset,
+    pub tools: JsonValue,
+}
@@ -0,0 +1,179 @@
+use crate::error::AppError::Forbidden;
+use crate::error::Result;
+use crate::preset::controller;
+use crate::preset::model::{Preset, PresetWithTools};
+use crate::tool::model::PipelineTool;
+use crate::user::AccessTokenClaims;
+use crate::{project, AppState};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::routing::{get, post};
+use axum::{debug_handler, Json, Router};
+use serde::Deserialize;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/presets", get(get_presets).post(create_preset))
+        .route(
+            "/presets/{preset_id}",
+            get(get_preset).put(update_preset).delete(delete_preset),
+        )
+        .route("/presets/{preset_id}/versions", get(get_preset_versions))
+        .route(
+            "/presets/{preset_id}/versions/{version}",
+            get(get_preset_version),
+        )
+        .route(
+            "/projects/{project_id}/tools/presets",
+            post(instantiate_preset),
+        )
+        .with_state(state)
+}
+
+/// Returns the preset if the user owns it.
+async fn get_owned_preset(
+    preset_id: Uuid,
+    user: &AccessTokenClaims,
+    state: &AppState,
+) -> Result<Preset> {
+    let preset = controller::get_preset(preset_id, state).await?;
+    if preset.user_id != user.sub {
+        return Err(Forbidden);
+    }
+    Ok(preset)
+}
+
+#[derive(Deserialize)]
+struct CreatePresetRequest {
+    name: String,
+    tools: Vec<PipelineTool>,
+}
+
+#[debug_handler]
+async fn create_preset(
+    State(state): State<AppState>,
+    user: AccessTokenClaims,
+    Json(request): Json<CreatePresetRequest>,
+) -> Result<(StatusCode, Json<PresetWithTools>)> {
+    let preset = controller::create_preset(user.sub, request.name, request.tools, &state).await?;
+    Ok((StatusCode::CREATED, Json(preset)))
+}
+
+#[debug_handler]
+async fn get_presets(
+    user: AccessTokenClaims,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<Preset>>> {
+    let presets = controller::get_presets(user.sub, &state).await?;
+    Ok(Json(presets))
+}
+
+#[debug_handler]
+async fn get_preset(
+    Path(preset_id): Path<Uuid>,
+    user: AccessTokenClaims,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse> {
+    let preset = get_owned_preset(preset_id, &user, &state).await?;
+    let version = controller::get_preset_version(preset.id, preset.version, &state).await?;
+
+    Ok(Json(PresetWithTools {
+        preset,
+        tools: version.tools,
+    }))
+}
+
+#[derive(Deserialize)]
+struct UpdatePresetRequest {
+    name: Option<String>,
+    /// The tools of the new version of the preset, the tools are kept if none are given.
+    tools: Option<Vec<PipelineTool>>,
+}
+
+#[debug_handler]
+async fn update_preset(
+    Path(preset_id): Path<Uuid>,
+    user: AccessTokenClaims,
+    State(state): State<AppState>,
+    Json(request): Json<UpdatePresetRequest>,
+) -> Result<impl IntoResponse> {
+    let preset = get_owned_preset(preset_id, &user, &state).await?;
+    let preset = controller::update_preset(preset.id, request.name, request.tools, &state).await?;
+    Ok(Json(preset))
+}
+
+#[debug_handler]
+async fn delete_preset(
+    Path(preset_id): Path<Uuid>,
+    user: AccessTokenClaims,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse> {
+    let preset = get_owned_preset(preset_id, &user, &state).await?;
+    controller::delete_preset(preset.id, &state).await?;
+    Ok(Json(preset))
+}
+
+#[debug_handler]
+async fn get_preset_versions(
+    Path(preset_id): Path<Uuid>,
+    user: AccessTokenClaims,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse> {
+    let preset = get_owned_preset(preset_id, &user, &state).await?;
+    let versions = controller::get_preset_versions(preset.id, &state).await?;
+    Ok(Json(versions))
+}
+
+#[debug_handler]
+async fn get_preset_version(
+    Path((preset_id, version)): Path<(Uuid, i32)>,
+    user: AccessTokenClaims,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse> {
+    let preset = get_owned_preset(preset_id, &user, &state).await?;
+    let version = controller::get_preset_version(preset.id, version, &state).await?;
+    Ok(Json(version))
+}
+
+#[derive(Deserialize)]
+struct InstantiatePresetRequest {
+    preset_id: Uuid,
+    /// The version of the preset to instantiate, the latest one if none is given.
+    version: Option<i32>,
+    /// The parameters to override, by the index of the tool in the preset.
+    #[serde(default)]
+    overrides: HashMap<usize, HashMap<String, serde_json::Value>>,
+    /// Appends the tools after the last tool of the project instead of replacing its tools.
+    #[serde(default)]
+    append: bool,
+}
+
+#[debug_handler]
+async fn instantiate_preset(
+    Path(project_id): Path<Uuid>,
+    user: AccessTokenClaims,
+    State(state): State<AppState>,
+    Json(request): Json<InstantiatePresetRequest>,
+) -> Result<impl IntoResponse> {
+    if !project::controller::can_modify(project_id, user.sub, &state).await? {
+        return Err(Forbidden);
+    }
+
+    let preset = get_owned_preset(request.preset_id, &user, &state).await?;
+    let version = request.version.unwrap_or(preset.version);
+    let version = controller::get_preset_version(preset.id, version, &state).await?;
+
+    let tools = controller::instantiate_preset(
+        project_id,
+        &version,
+        request.overrides,
+        request.append,
+        &state,
+    )
+    .await?;
+
+    Ok(Json(tools))
+}
@@ -1,6 +1,6 @@
 use crate::error::{AppError, Result};
 use crate::project::model::Project;
-use crate::AppState;
+use crate::{config, tool, AppState};
 use chrono::Utc;
 use tracing::info;
 use uuid::Uuid;
@@ -61,12 +61,35 @@ pub async fn get_project(project_id: Uuid, state: AppState) -> Result<Project> {
     Ok(project)
 }
 
+/// Deletes a project along with its images, tools and their results, its files being removed
+/// once the rows are.
 pub async fn delete_project(project_id: Uuid, state: AppState) -> Result<()> {
     info!("Deleting project with ID: {}", project_id);
-    let _ = sqlx::query!("DELETE FROM projects WHERE id = $1", project_id)
-        .execute(&state.db_pool)
+    let mut transaction = state.db_pool.begin().await?;
+
+    let image_versions =
+        tool::controller::lock_image_versions(project_id, &mut transaction).await?;
+    let mut cleanup =
+        tool::controller::delete_image_versions(image_versions, &mut transaction).await?;
+
+    // the jobs and preview versions are deleted along with the tools, images and project
+    sqlx::query!("DELETE FROM tools WHERE project_id = $1", project_id)
+        .execute(&mut *transaction)
+        .await?;
+
+    sqlx::query!("DELETE FROM images WHERE project_id = $1", project_id)
+        .execute(&mut *transaction)
         .await?;
 
+    sqlx::query!("DELETE FROM projects WHERE id = $1", project_id)
+        .execute(&mut *transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    cleanup.remove_folder(config::generate_project_folder_uri(project_id));
+    cleanup.run(&state).await?;
+
     info!("Deleted project with ID: {}", project_id);
     Ok(())
 }
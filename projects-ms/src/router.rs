@@ -1,4 +1,4 @@
-use crate::{image, project, tool, AppState};
+use crate::{image, job, preset, project, tool, AppState};
 use axum::routing::get;
 use axum::Router;
 
@@ -9,6 +9,8 @@ pub fn router(state: AppState) -> Router {
             .route("/health", get(health_check))
             .merge(tool::router::router(state.clone()))
             .merge(image::router::router(state.clone()))
+            .merge(job::router::router(state.clone()))
+            .merge(preset::router::router(state.clone()))
             .merge(project::router::router(state.clone())),
     )
 }
@@ -0,0 +1,215 @@
+use crate::config::SignedUrlScope;
+use crate::error::{AppError, Result};
+use crate::project;
+use crate::state::AppState;
+use crate::user::AccessTokenClaims;
+use axum::extract::{FromRequestParts, Query};
+use axum::http::request::Parts;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// The query of a signed download URL, e.g. `?expires=1738000000&scope=image&signature=...`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct UrlSignature {
+    expires: i64,
+    scope: SignedUrlScope,
+    signature: String,
+}
+
+/// Who is downloading a file, either a user of the project or anyone with a signed URL, e.g. an
+/// `<img>` of another origin.
+pub enum DownloadAccess {
+    User(AccessTokenClaims),
+    Signed(UrlSignature),
+}
+
+impl FromRequestParts<AppState> for DownloadAccess {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self> {
+        let has_signature = parts
+            .uri
+            .query()
+            .is_some_and(|query| query.contains("signature="));
+
+        if has_signature {
+            let Query(signature) = Query::<UrlSignature>::from_request_parts(parts, state)
+                .await
+                .map_err(|_| AppError::Unauthorized)?;
+            return Ok(DownloadAccess::Signed(signature));
+        }
+
+        AccessTokenClaims::from_request_parts(parts, state)
+            .await
+            .map(DownloadAccess::User)
+    }
+}
+
+impl DownloadAccess {
+    /// Whether the file of the project can be downloaded, `file_id` being the id of the image,
+    /// image version or preview version.
+    pub async fn can_download(
+        &self,
+        project_id: Uuid,
+        file_id: Uuid,
+        state: &AppState,
+    ) -> Result<bool> {
+        match self {
+            DownloadAccess::User(user) => {
+                project::controller::can_modify(project_id, user.sub, state).await
+            }
+            DownloadAccess::Signed(signature) => Ok(verify(
+                project_id,
+                file_id,
+                signature,
+                &state.config.picturas_url_signing_key,
+            )),
+        }
+    }
+}
+
+/// Signs the URL of a file of a project, valid for the configured time to live, for the file
+/// alone or for every file of the project depending on the configured scope.
+pub fn sign_url(url: String, project_id: Uuid, file_id: Uuid, state: &AppState) -> String {
+    let config = &state.config;
+    let expires = Utc::now().timestamp() + config.picturas_signed_url_ttl_secs as i64;
+    let scope = config.picturas_signed_url_scope;
+
+    let signature = sign(
+        project_id,
+        file_id,
+        scope,
+        expires,
+        &config.picturas_url_signing_key,
+    );
+
+    format!(
+        "{url}?expires={expires}&scope={}&signature={signature}",
+        scope.as_str()
+    )
+}
+
+fn sign(project_id: Uuid, file_id: Uuid, scope: SignedUrlScope, expires: i64, key: &str) -> String {
+    hex::encode(
+        mac(project_id, file_id, scope, expires, key)
+            .finalize()
+            .into_bytes(),
+    )
+}
+
+fn verify(project_id: Uuid, file_id: Uuid, signature: &UrlSignature, key: &str) -> bool {
+    if Utc::now().timestamp() > signature.expires {
+        return false;
+    }
+
+    let Ok(bytes) = hex::decode(&signature.signature) else {
+        return false;
+    };
+
+    // the comparison takes the same time wherever the signatures differ
+    mac(project_id, file_id, signature.scope, signature.expires, key)
+        .verify_slice(&bytes)
+        .is_ok()
+}
+
+fn mac(
+    project_id: Uuid,
+    file_id: Uuid,
+    scope: SignedUrlScope,
+    expires: i64,
+    key: &str,
+) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any size");
+
+    // a URL signed for the whole project doesn't depend on the file it points to
+    let subject = match scope {
+        SignedUrlScope::Image => format!("{project_id}/{file_id}"),
+        SignedUrlScope::Project => format!("{project_id}/*"),
+    };
+    mac.update(format!("{}:{subject}:{expires}", scope.as_str()).as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::SignedUrlScope;
+    use crate::signed_url::{sign, verify, UrlSignature};
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    const KEY: &str = "signing-key";
+
+    fn signature(project_id: Uuid, file_id: Uuid, scope: SignedUrlScope, ttl: i64) -> UrlSignature {
+        let expires = Utc::now().timestamp() + ttl;
+        UrlSignature {
+            expires,
+            scope,
+            signature: sign(project_id, file_id, scope, expires, KEY),
+        }
+    }
+
+    #[test]
+    fn test_verify_image_scope() {
+        let (project_id, file_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let signature = signature(project_id, file_id, SignedUrlScope::Image, 60);
+
+        assert!(verify(project_id, file_id, &signature, KEY));
+        assert!(!verify(project_id, Uuid::new_v4(), &signature, KEY));
+        assert!(!verify(Uuid::new_v4(), file_id, &signature, KEY));
+        assert!(!verify(project_id, file_id, &signature, "another-key"));
+    }
+
+    #[test]
+    fn test_verify_project_scope() {
+        let (project_id, file_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let signature = signature(project_id, file_id, SignedUrlScope::Project, 60);
+
+        assert!(verify(project_id, Uuid::new_v4(), &signature, KEY));
+        assert!(!verify(Uuid::new_v4(), file_id, &signature, KEY));
+    }
+
+    #[test]
+    fn test_verify_expired() {
+        let (project_id, file_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let signature = signature(project_id, file_id, SignedUrlScope::Image, -1);
+
+        assert!(!verify(project_id, file_id, &signature, KEY));
+    }
+
+    #[test]
+    fn test_verify_tampered() {
+        let (project_id, file_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let signature = signature(project_id, file_id, SignedUrlScope::Image, 60);
+
+        // a later expiry or a wider scope than the ones signed
+        let extended = UrlSignature {
+            expires: signature.expires + 3600,
+            ..signature.clone()
+        };
+        assert!(!verify(project_id, file_id, &extended, KEY));
+
+        let widened = UrlSignature {
+            scope: SignedUrlScope::Project,
+            ..signature.clone()
+        };
+        assert!(!verify(project_id, file_id, &widened, KEY));
+
+        let mut bytes = hex::decode(&signature.signature).unwrap();
+        bytes[0] ^= 1;
+        let flipped = UrlSignature {
+            signature: hex::encode(bytes),
+            ..signature.clone()
+        };
+        assert!(!verify(project_id, file_id, &flipped, KEY));
+
+        let not_hex = UrlSignature {
+            signature: "not hex".to_string(),
+            ..signature
+        };
+        assert!(!verify(project_id, file_id, &not_hex, KEY));
+    }
+}
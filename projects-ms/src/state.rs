@@ -1,11 +1,14 @@
 use crate::config::Config;
+use crate::storage::Storage;
 use crate::tool::amqp::rabbit_controller::RabbitMqController;
-use crate::tool::queue::QueuedImageApplyTool;
+use crate::tool::catalogue::ToolCatalogue;
+use crate::tool::live_preview::LivePreviews;
 use axum::extract::ws::Message;
 use dashmap::DashMap;
 use sqlx::PgPool;
 use std::sync::Arc;
 use tokio::sync::mpsc::Sender;
+use tokio::sync::Notify;
 use uuid::Uuid;
 
 #[derive(Clone)]
@@ -13,6 +16,9 @@ pub struct AppState {
     pub db_pool: PgPool,
     pub config: Arc<Config>,
     pub rabbit_mq_controller: Arc<RabbitMqController>,
-    pub queued_tools: Arc<DashMap<Uuid, (Uuid, QueuedImageApplyTool)>>, // queue_uuid, (current_tool_uuid, tool)
     pub connected_ws_clients: Arc<DashMap<(Uuid, Uuid), Sender<Message>>>, // project_uuid, user_uuid -> Sender<Message>
+    pub scheduler_notify: Arc<Notify>,
+    pub live_previews: Arc<LivePreviews>,
+    pub tool_catalogue: Arc<ToolCatalogue>,
+    pub storage: Arc<dyn Storage>,
 }
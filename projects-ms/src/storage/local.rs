@@ -0,0 +1,77 @@
+use crate::storage::{Storage, StorageError};
+use async_trait::async_trait;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Stores the files in a folder, which the tools must mount at the same path.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    fn path(&self, key: &Path) -> PathBuf {
+        self.root.join(key)
+    }
+
+    async fn create_parent(path: &Path) -> Result<(), StorageError> {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Ignores the error of deleting something that doesn't exist.
+fn ignore_not_found(result: std::io::Result<()>) -> Result<(), StorageError> {
+    match result {
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+        result => Ok(result?),
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    fn uri(&self, key: &Path) -> String {
+        format!("file://{}", self.path(key).display())
+    }
+
+    async fn read(&self, key: &Path) -> Result<Vec<u8>, StorageError> {
+        Ok(tokio::fs::read(self.path(key)).await?)
+    }
+
+    async fn write(&self, key: &Path, bytes: Vec<u8>) -> Result<(), StorageError> {
+        let path = self.path(key);
+        Self::create_parent(&path).await?;
+        Ok(tokio::fs::write(path, bytes).await?)
+    }
+
+    async fn write_file(&self, key: &Path, source: &Path) -> Result<(), StorageError> {
+        let path = self.path(key);
+        Self::create_parent(&path).await?;
+        tokio::fs::copy(source, path).await?;
+        Ok(())
+    }
+
+    async fn exists(&self, key: &Path) -> Result<bool, StorageError> {
+        Ok(tokio::fs::try_exists(self.path(key)).await?)
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> Result<(), StorageError> {
+        let to = self.path(to);
+        Self::create_parent(&to).await?;
+        tokio::fs::copy(self.path(from), to).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &Path) -> Result<(), StorageError> {
+        ignore_not_found(tokio::fs::remove_file(self.path(key)).await)
+    }
+
+    async fn delete_folder(&self, key: &Path) -> Result<(), StorageError> {
+        ignore_not_found(tokio::fs::remove_dir_all(self.path(key)).await)
+    }
+}
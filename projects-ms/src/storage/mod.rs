@@ -0,0 +1,59 @@
+use crate::config::{Config, StorageKind};
+use async_trait::async_trait;
+use std::path::Path;
+use std::sync::Arc;
+use thiserror::Error;
+
+pub mod local;
+pub mod s3;
+
+/// Where the images and the results of the tools are stored, each file addressed by a key
+/// relative to the root of the storage, e.g. `<project>/<image>.png`.
+/// The tools are given scheme-qualified URIs of the files, so they don't need to share a
+/// volume with this service.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// The URI of a file the tools can read from and write to, e.g.
+    /// `file:///images/<project>/<image>.png` or `s3://picturas/<project>/<image>.png`.
+    fn uri(&self, key: &Path) -> String;
+
+    async fn read(&self, key: &Path) -> Result<Vec<u8>, StorageError>;
+
+    async fn write(&self, key: &Path, bytes: Vec<u8>) -> Result<(), StorageError>;
+
+    /// Writes the contents of a local file, without reading it whole into memory.
+    async fn write_file(&self, key: &Path, source: &Path) -> Result<(), StorageError>;
+
+    async fn exists(&self, key: &Path) -> Result<bool, StorageError>;
+
+    async fn copy(&self, from: &Path, to: &Path) -> Result<(), StorageError>;
+
+    /// Deletes a file, doing nothing if it doesn't exist.
+    async fn delete(&self, key: &Path) -> Result<(), StorageError>;
+
+    /// Deletes every file under a folder, doing nothing if it doesn't exist.
+    async fn delete_folder(&self, key: &Path) -> Result<(), StorageError>;
+}
+
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("s3 error: {0}")]
+    S3(#[from] ::s3::error::S3Error),
+    #[error("s3 credentials error: {0}")]
+    S3Credentials(#[from] ::s3::creds::error::CredentialsError),
+    #[error("invalid storage configuration: {0}")]
+    Config(&'static str),
+}
+
+pub fn from_config(config: &Config) -> Result<Arc<dyn Storage>, StorageError> {
+    let storage: Arc<dyn Storage> = match config.picturas_storage {
+        StorageKind::File => Arc::new(local::LocalStorage::new(
+            config.picturas_image_folder.clone(),
+        )),
+        StorageKind::S3 => Arc::new(s3::S3Storage::new(config)?),
+    };
+
+    Ok(storage)
+}
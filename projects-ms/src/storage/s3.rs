@@ -0,0 +1,110 @@
+use crate::config::Config;
+use crate::storage::{Storage, StorageError};
+use async_trait::async_trait;
+use s3::creds::Credentials;
+use s3::error::S3Error;
+use s3::{Bucket, Region};
+use std::path::Path;
+
+/// Stores the files in a bucket of an S3-compatible object storage, e.g. MinIO.
+pub struct S3Storage {
+    bucket: Box<Bucket>,
+}
+
+impl S3Storage {
+    pub fn new(config: &Config) -> Result<Self, StorageError> {
+        let bucket_name = config
+            .s3_bucket
+            .as_deref()
+            .ok_or(StorageError::Config("S3_BUCKET is required"))?;
+
+        let region = match &config.s3_endpoint {
+            Some(endpoint) => Region::Custom {
+                region: config.s3_region.clone(),
+                endpoint: endpoint.clone(),
+            },
+            None => config
+                .s3_region
+                .parse()
+                .map_err(|_| StorageError::Config("S3_REGION isn't valid"))?,
+        };
+
+        let credentials = Credentials::new(
+            config.s3_access_key.as_deref(),
+            config.s3_secret_key.as_deref(),
+            None,
+            None,
+            None,
+        )?;
+
+        // most of the S3-compatible storages only support path-style requests
+        let bucket = Bucket::new(bucket_name, region, credentials)?.with_path_style();
+
+        Ok(Self { bucket })
+    }
+
+    fn key(key: &Path) -> String {
+        key.to_string_lossy().into_owned()
+    }
+}
+
+fn is_not_found(err: &S3Error) -> bool {
+    matches!(err, S3Error::HttpFailWithBody(404, _))
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    fn uri(&self, key: &Path) -> String {
+        format!("s3://{}/{}", self.bucket.name, Self::key(key))
+    }
+
+    async fn read(&self, key: &Path) -> Result<Vec<u8>, StorageError> {
+        let response = self.bucket.get_object(Self::key(key)).await?;
+        Ok(response.to_vec())
+    }
+
+    async fn write(&self, key: &Path, bytes: Vec<u8>) -> Result<(), StorageError> {
+        self.bucket.put_object(Self::key(key), &bytes).await?;
+        Ok(())
+    }
+
+    async fn write_file(&self, key: &Path, source: &Path) -> Result<(), StorageError> {
+        let mut file = tokio::fs::File::open(source).await?;
+        self.bucket
+            .put_object_stream(&mut file, Self::key(key))
+            .await?;
+        Ok(())
+    }
+
+    async fn exists(&self, key: &Path) -> Result<bool, StorageError> {
+        match self.bucket.head_object(Self::key(key)).await {
+            Ok(_) => Ok(true),
+            Err(err) if is_not_found(&err) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> Result<(), StorageError> {
+        self.bucket
+            .copy_object_internal(Self::key(from), Self::key(to))
+            .await?;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &Path) -> Result<(), StorageError> {
+        match self.bucket.delete_object(Self::key(key)).await {
+            Err(err) if !is_not_found(&err) => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn delete_folder(&self, key: &Path) -> Result<(), StorageError> {
+        let prefix = format!("{}/", Self::key(key));
+        for page in self.bucket.list(prefix, None).await? {
+            for object in page.contents {
+                self.bucket.delete_object(object.key).await?;
+            }
+        }
+        Ok(())
+    }
+}
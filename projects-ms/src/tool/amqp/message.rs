@@ -64,3 +64,21 @@ pub struct Metadata {
     pub processing_time: f64,
     pub microservice: String,
 }
+
+/// Sent by the tools to the control exchange, describing the procedures they handle.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnnouncementMessage {
+    pub message_id: String,
+    pub timestamp: DateTime<Utc>,
+    pub microservice: String,
+    pub tools: Vec<ToolDescription>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolDescription {
+    pub procedure: String,
+    /// The JSON Schema of the parameters of the procedure.
+    pub parameters_schema: Value,
+}
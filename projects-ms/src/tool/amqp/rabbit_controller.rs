@@ -1,15 +1,19 @@
-use crate::tool::amqp::message::{RequestMessage, ResponseMessage};
+use crate::tool::amqp::message::RequestMessage;
 use crate::{AppState, Config};
 use futures_util::StreamExt;
+use lapin::acker::Acker;
 use lapin::options::{
-    BasicAckOptions, BasicPublishOptions, ExchangeDeclareOptions, QueueBindOptions,
-    QueueDeclareOptions,
+    BasicAckOptions, BasicNackOptions, BasicPublishOptions, ExchangeDeclareOptions,
+    QueueBindOptions, QueueDeclareOptions,
 };
-use lapin::types::FieldTable;
+use lapin::protocol::{AMQPErrorKind, AMQPSoftError};
+use lapin::types::{AMQPValue, FieldTable};
 use lapin::{BasicProperties, Channel, Connection, Consumer, ExchangeKind};
+use serde::de::DeserializeOwned;
 use std::collections::HashMap;
+use std::time::Duration;
 use thiserror::Error;
-use tracing::info;
+use tracing::{info, warn};
 
 #[derive(Debug, Clone)]
 pub struct ToolQueue {
@@ -18,45 +22,87 @@ pub struct ToolQueue {
 }
 
 pub struct RabbitMqController {
+    connection: Connection,
     channel: Channel,
     exchange: String,
     procedure_routing_key_map: HashMap<String, String>,
 }
 
 impl RabbitMqController {
-    pub async fn new(concurrent_requests: u16, config: &Config) -> Self {
-        let (_connection, channel) = connect(concurrent_requests, config).await;
+    pub async fn new(
+        concurrent_requests: u16,
+        config: &Config,
+    ) -> Result<Self, RabbitMqControllerError> {
+        let (connection, channel) = connect(concurrent_requests, config).await;
 
         let exchange = &config.rabbitmq_results_exchange;
+        let dead_letter_exchange = &config.rabbitmq_dead_letter_exchange;
 
         let tool_queues = &config.picturas_available_tools;
-        setup_exchange_and_queues(&channel, exchange, tool_queues).await;
+        setup_exchange_and_queues(
+            &connection,
+            &channel,
+            exchange,
+            dead_letter_exchange,
+            config.rabbitmq_max_priority,
+            tool_queues,
+        )
+        .await?;
 
         let procudure_routing_key_map = tool_queues
             .iter()
             .map(|tool| (tool.name.clone(), tool.routing_key.clone()))
             .collect();
 
-        Self {
+        Ok(Self {
+            connection,
             channel,
             exchange: exchange.clone(),
             procedure_routing_key_map: procudure_routing_key_map,
-        }
+        })
     }
 
-    pub async fn create_consumer(&self, state: &AppState) -> RabbitMqConsumer {
+    pub async fn create_consumer(
+        &self,
+        state: &AppState,
+    ) -> Result<RabbitMqConsumer, RabbitMqControllerError> {
         let exchange = &state.config.rabbitmq_results_exchange;
         let routing_key = &state.config.rabbitmq_results_routing_key;
-        let consumer = create_results_consumer(&self.channel, exchange, routing_key).await;
+        let dead_letter_exchange = &state.config.rabbitmq_dead_letter_exchange;
+        let consumer = create_results_consumer(
+            &self.connection,
+            &self.channel,
+            exchange,
+            dead_letter_exchange,
+            routing_key,
+        )
+        .await?;
 
         info!(exchange, routing_key, "Created consumer");
 
-        RabbitMqConsumer { consumer }
+        Ok(RabbitMqConsumer { consumer })
+    }
+
+    /// Creates a consumer of the announcements the tools send to the control exchange.
+    pub async fn create_control_consumer(
+        &self,
+        state: &AppState,
+    ) -> Result<RabbitMqConsumer, RabbitMqControllerError> {
+        let exchange = &state.config.rabbitmq_control_exchange;
+        let consumer = create_control_consumer(&self.channel, exchange).await?;
+
+        info!(exchange, "Created control consumer");
+
+        Ok(RabbitMqConsumer { consumer })
     }
 
+    /// Publishes a request to the queue of its tool, it is dropped if not consumed before `expiration`.
+    /// Requests with a higher `priority` are consumed first.
     pub async fn publish_request(
         &self,
         request: RequestMessage,
+        expiration: Duration,
+        priority: u8,
     ) -> Result<(), RabbitMqControllerError> {
         let procedure = request.procedure.clone();
         let routing_key = self
@@ -70,7 +116,9 @@ impl RabbitMqController {
                 routing_key,
                 BasicPublishOptions::default(),
                 &serde_json::to_vec(&request)?,
-                BasicProperties::default(),
+                BasicProperties::default()
+                    .with_expiration(expiration.as_millis().to_string().into())
+                    .with_priority(priority),
             )
             .await?;
         Ok(())
@@ -81,17 +129,64 @@ pub struct RabbitMqConsumer {
     consumer: Consumer,
 }
 
+/// Settles a received message once it is handled, so a message whose handling failed isn't
+/// lost.
+pub struct MessageAcker {
+    acker: Acker,
+}
+
+impl MessageAcker {
+    pub async fn ack(&self) -> Result<(), RabbitMqControllerError> {
+        self.acker.ack(BasicAckOptions::default()).await?;
+        Ok(())
+    }
+
+    /// Rejects the message, which the broker sends to the dead-letter exchange.
+    pub async fn reject(&self) -> Result<(), RabbitMqControllerError> {
+        self.acker
+            .nack(BasicNackOptions {
+                requeue: false,
+                ..Default::default()
+            })
+            .await?;
+        Ok(())
+    }
+}
+
 impl RabbitMqConsumer {
-    pub async fn next_result_message(
+    /// Receives the next message, acked as soon as it is received.
+    pub async fn next_message<T: DeserializeOwned>(
         &mut self,
-    ) -> Result<ResponseMessage, RabbitMqControllerError> {
+    ) -> Result<T, RabbitMqControllerError> {
+        let (message, acker) = self.next_delivery().await?;
+        acker.ack().await?;
+        Ok(message)
+    }
+
+    /// Receives the next message, which must be settled with the returned [`MessageAcker`].
+    pub async fn next_delivery<T: DeserializeOwned>(
+        &mut self,
+    ) -> Result<(T, MessageAcker), RabbitMqControllerError> {
         let delivery = self.consumer.next().await;
         match delivery {
-            Some(Ok(delivery)) => {
-                let message = serde_json::from_slice(&delivery.data)?;
-                delivery.ack(BasicAckOptions::default()).await?;
-                Ok(message)
-            }
+            Some(Ok(delivery)) => match serde_json::from_slice(&delivery.data) {
+                Ok(message) => Ok((
+                    message,
+                    MessageAcker {
+                        acker: delivery.acker,
+                    },
+                )),
+                Err(error) => {
+                    // the message will never be readable, send it to the dead-letter exchange
+                    delivery
+                        .nack(BasicNackOptions {
+                            requeue: false,
+                            ..Default::default()
+                        })
+                        .await?;
+                    Err(RabbitMqControllerError::SerdeJson(error))
+                }
+            },
             Some(Err(error)) => Err(RabbitMqControllerError::LapinError(error)),
             None => Err(RabbitMqControllerError::EmptyIterator),
         }
@@ -129,7 +224,121 @@ async fn connect(concurrent_requests: u16, config: &Config) -> (Connection, Chan
     (connection, channel)
 }
 
-async fn setup_exchange_and_queues(channel: &Channel, exchange: &str, tool_queues: &[ToolQueue]) {
+/// Arguments of the queues whose rejected messages should go to the dead-letter exchange.
+fn dead_letter_arguments(dead_letter_exchange: &str) -> FieldTable {
+    let mut arguments = FieldTable::default();
+    arguments.insert(
+        "x-dead-letter-exchange".into(),
+        AMQPValue::LongString(dead_letter_exchange.into()),
+    );
+    arguments
+}
+
+/// Declares a durable queue with the given arguments.
+/// The broker refuses to change the arguments of an existing queue, e.g. one declared before
+/// dead-lettering and priorities were introduced, so such a queue is used as it is, without
+/// them. To get them, stop the services using it, delete it (e.g. `rabbitmqctl delete_queue
+/// <name>`) and start this service again, which declares it anew.
+async fn declare_queue(
+    connection: &Connection,
+    name: &str,
+    arguments: FieldTable,
+) -> Result<(), RabbitMqControllerError> {
+    // the broker closes the channel of a refused declaration, so it gets a channel of its own
+    let channel = connection.create_channel().await?;
+    let declared = channel
+        .queue_declare(
+            name,
+            QueueDeclareOptions {
+                durable: true,
+                ..Default::default()
+            },
+            arguments,
+        )
+        .await;
+
+    match declared {
+        Ok(_) => {
+            channel.close(200, "Declared").await?;
+            Ok(())
+        }
+        Err(lapin::Error::ProtocolError(error))
+            if matches!(
+                error.kind(),
+                AMQPErrorKind::Soft(AMQPSoftError::PRECONDITIONFAILED)
+            ) =>
+        {
+            warn!(
+                queue = name,
+                "The queue exists with other arguments, using it as it is, delete it to declare it anew: {}",
+                error.get_message()
+            );
+
+            let channel = connection.create_channel().await?;
+            channel
+                .queue_declare(
+                    name,
+                    QueueDeclareOptions {
+                        passive: true,
+                        ..Default::default()
+                    },
+                    FieldTable::default(),
+                )
+                .await?;
+            channel.close(200, "Declared").await?;
+            Ok(())
+        }
+        Err(error) => Err(error.into()),
+    }
+}
+
+async fn setup_exchange_and_queues(
+    connection: &Connection,
+    channel: &Channel,
+    exchange: &str,
+    dead_letter_exchange: &str,
+    max_priority: u8,
+    tool_queues: &[ToolQueue],
+) -> Result<(), RabbitMqControllerError> {
+    const DEAD_LETTER_QUEUE: &str = "dead-letters";
+    channel
+        .exchange_declare(
+            dead_letter_exchange,
+            ExchangeKind::Fanout,
+            ExchangeDeclareOptions {
+                durable: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await?;
+
+    channel
+        .queue_declare(
+            DEAD_LETTER_QUEUE,
+            QueueDeclareOptions {
+                durable: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await?;
+
+    channel
+        .queue_bind(
+            DEAD_LETTER_QUEUE,
+            dead_letter_exchange,
+            "",
+            QueueBindOptions::default(),
+            FieldTable::default(),
+        )
+        .await?;
+
+    info!(
+        dead_letter_exchange,
+        DEAD_LETTER_QUEUE, "Declared dead-letter exchange"
+    );
+
     channel
         .exchange_declare(
             exchange,
@@ -140,25 +349,20 @@ async fn setup_exchange_and_queues(channel: &Channel, exchange: &str, tool_queue
             },
             FieldTable::default(),
         )
-        .await
-        .expect("Failed to declare the exchange");
+        .await?;
 
     info!(exchange, "Declared exchange");
 
     for tool in tool_queues {
-        channel
-            .queue_declare(
-                &tool.name,
-                QueueDeclareOptions {
-                    durable: true,
-                    ..Default::default()
-                },
-                FieldTable::default(),
-            )
-            .await
-            .expect("Failed to declare the queue");
+        let mut arguments = dead_letter_arguments(dead_letter_exchange);
+        arguments.insert(
+            "x-max-priority".into(),
+            AMQPValue::ShortShortUInt(max_priority),
+        );
+
+        declare_queue(connection, &tool.name, arguments).await?;
 
-        info!(tool.name, "Declared durable queue");
+        info!(tool.name, max_priority, "Declared durable priority queue");
 
         channel
             .queue_bind(
@@ -168,60 +372,113 @@ async fn setup_exchange_and_queues(channel: &Channel, exchange: &str, tool_queue
                 QueueBindOptions::default(),
                 FieldTable::default(),
             )
-            .await
-            .expect("Failed to bind queue to exchange");
+            .await?;
 
         info!(tool.name, tool.routing_key, "Bound queue to exchange");
     }
+
+    Ok(())
 }
 
 async fn create_results_consumer(
+    connection: &Connection,
     channel: &Channel,
     exchange: &str,
+    dead_letter_exchange: &str,
     results_routing_key: &str,
-) -> Consumer {
+) -> Result<Consumer, RabbitMqControllerError> {
     const RESULTS_QUEUE: &str = "results";
+    declare_queue(
+        connection,
+        RESULTS_QUEUE,
+        dead_letter_arguments(dead_letter_exchange),
+    )
+    .await?;
+
+    info!(RESULTS_QUEUE, "Declared results queue");
+
     channel
-        .queue_declare(
+        .queue_bind(
             RESULTS_QUEUE,
-            QueueDeclareOptions {
+            exchange,
+            results_routing_key,
+            QueueBindOptions::default(),
+            FieldTable::default(),
+        )
+        .await?;
+
+    info!(
+        RESULTS_QUEUE,
+        exchange, results_routing_key, "Bound results queue to exchange"
+    );
+
+    let consumer = channel
+        .basic_consume(RESULTS_QUEUE, "", Default::default(), FieldTable::default())
+        .await?;
+    Ok(consumer)
+}
+
+async fn create_control_consumer(
+    channel: &Channel,
+    exchange: &str,
+) -> Result<Consumer, RabbitMqControllerError> {
+    channel
+        .exchange_declare(
+            exchange,
+            ExchangeKind::Fanout,
+            ExchangeDeclareOptions {
                 durable: true,
                 ..Default::default()
             },
             FieldTable::default(),
         )
-        .await
-        .expect("Failed to declare the results queue");
+        .await?;
 
-    info!(RESULTS_QUEUE, "Declared results queue");
+    // every instance gets every announcement, and the tools announce themselves periodically,
+    // so the queue doesn't need to outlive the instance
+    let queue = channel
+        .queue_declare(
+            "",
+            QueueDeclareOptions {
+                exclusive: true,
+                auto_delete: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await?;
 
     channel
         .queue_bind(
-            RESULTS_QUEUE,
+            queue.name().as_str(),
             exchange,
-            results_routing_key,
+            "",
             QueueBindOptions::default(),
             FieldTable::default(),
         )
-        .await
-        .expect("Failed to bind results queue to exchange");
+        .await?;
 
     info!(
-        RESULTS_QUEUE,
-        exchange, results_routing_key, "Bound results queue to exchange"
+        queue = queue.name().as_str(),
+        exchange, "Bound control queue to exchange"
     );
 
-    channel
-        .basic_consume(RESULTS_QUEUE, "", Default::default(), FieldTable::default())
-        .await
-        .expect("Failed to register a consumer")
+    let consumer = channel
+        .basic_consume(
+            queue.name().as_str(),
+            "",
+            Default::default(),
+            FieldTable::default(),
+        )
+        .await?;
+    Ok(consumer)
 }
 
 #[derive(Debug, Error)]
 pub enum RabbitMqControllerError {
     #[error("Failed to serialize or deserialize JSON: {0}")]
     SerdeJson(#[from] serde_json::Error),
-    #[error("Failed to publish message: {0}")]
+    #[error("RabbitMQ error: {0}")]
     LapinError(#[from] lapin::Error),
     #[error("Iterator is empty")]
     EmptyIterator,
@@ -0,0 +1,91 @@
+use crate::error::Result;
+use crate::tool::model::CachedResult;
+use crate::{config, AppState};
+use chrono::Utc;
+use sqlx::PgConnection;
+use tracing::info;
+
+/// Takes a reference to a cached result, if there is one with the given key.
+pub async fn acquire(key: &str, transaction: &mut PgConnection) -> Result<Option<CachedResult>> {
+    let cached_result = sqlx::query_as!(
+        CachedResult,
+        "UPDATE result_cache SET ref_count = ref_count + 1 WHERE key = $1 RETURNING key, text_result, ref_count, created_at",
+        key
+    )
+    .fetch_optional(transaction)
+    .await?;
+
+    Ok(cached_result)
+}
+
+/// Stores a result a tool just wrote to the cache, taking a reference to it.
+/// If another job stored the same result meanwhile, a reference to that one is taken instead.
+pub async fn store(
+    key: &str,
+    text_result: Option<&str>,
+    transaction: &mut PgConnection,
+) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO result_cache (key, text_result, ref_count, created_at) VALUES ($1, $2, 1, $3) ON CONFLICT (key) DO UPDATE SET ref_count = result_cache.ref_count + 1",
+        key,
+        text_result,
+        Utc::now()
+    )
+    .execute(transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// Whether a result is cached, in which case its file must be kept.
+pub async fn is_cached(key: &str, state: &AppState) -> Result<bool> {
+    let cached = sqlx::query_scalar!("SELECT key FROM result_cache WHERE key = $1", key)
+        .fetch_optional(&state.db_pool)
+        .await?
+        .is_some();
+
+    Ok(cached)
+}
+
+/// Releases a reference to each of the given results, deleting the ones no longer used.
+pub async fn release(keys: &[String], state: &AppState) -> Result<()> {
+    let mut transaction = state.db_pool.begin().await?;
+    let mut deleted_keys = vec![];
+
+    for key in keys {
+        let ref_count = sqlx::query_scalar!(
+            "UPDATE result_cache SET ref_count = ref_count - 1 WHERE key = $1 RETURNING ref_count",
+            key
+        )
+        .fetch_optional(&mut *transaction)
+        .await?;
+
+        if ref_count.is_some_and(|ref_count| ref_count <= 0) {
+            sqlx::query!("DELETE FROM result_cache WHERE key = $1", key)
+                .execute(&mut *transaction)
+                .await?;
+
+            deleted_keys.push(key);
+        }
+    }
+
+    transaction.commit().await?;
+
+    // the files are only removed once their rows are gone, so no one can take a reference to a
+    // result whose file is missing
+    for key in deleted_keys {
+        // the same result may have been stored again since
+        if is_cached(key, state).await? {
+            continue;
+        }
+
+        let _ = state
+            .storage
+            .delete(&config::generate_cached_result_uri(key))
+            .await;
+
+        info!(key, "Deleted cached result");
+    }
+
+    Ok(())
+}
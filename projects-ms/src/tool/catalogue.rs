@@ -0,0 +1,123 @@
+use crate::error::{AppError, Result};
+use crate::tool::amqp::message::AnnouncementMessage;
+use crate::tool::amqp::rabbit_controller::RabbitMqConsumer;
+use crate::user::AccessTokenClaims;
+use crate::AppState;
+use axum::extract::{Path, State};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::{debug_handler, Json, Router};
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::Serialize;
+use serde_json::Value;
+use tracing::{error, info};
+
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/tools", get(get_catalogue))
+        .route("/tools/{procedure}", get(get_catalogue_entry))
+        .with_state(state)
+}
+
+/// A procedure announced by a tool.
+#[derive(Debug, Clone, Serialize)]
+pub struct CatalogueEntry {
+    pub procedure: String,
+    /// The JSON Schema of the parameters of the procedure.
+    pub parameters_schema: Value,
+    /// The tool that last announced the procedure.
+    pub microservice: String,
+    /// The date and time the procedure was last announced.
+    pub announced_at: DateTime<Utc>,
+}
+
+/// The procedures the tools announced, each one forgotten if no tool announces it again before
+/// it expires.
+#[derive(Default)]
+pub struct ToolCatalogue {
+    tools: DashMap<String, CatalogueEntry>, // procedure -> CatalogueEntry
+}
+
+impl ToolCatalogue {
+    fn announce(&self, message: AnnouncementMessage) {
+        let announced_at = Utc::now();
+        for tool in message.tools {
+            self.tools.insert(
+                tool.procedure.clone(),
+                CatalogueEntry {
+                    procedure: tool.procedure,
+                    parameters_schema: tool.parameters_schema,
+                    microservice: message.microservice.clone(),
+                    announced_at,
+                },
+            );
+        }
+    }
+}
+
+fn is_expired(entry: &CatalogueEntry, state: &AppState) -> bool {
+    let expiration =
+        chrono::Duration::seconds(state.config.picturas_catalogue_expiration_secs as i64);
+    entry.announced_at + expiration < Utc::now()
+}
+
+/// Returns the procedure if a tool handling it is available.
+pub fn get_tool(procedure: &str, state: &AppState) -> Option<CatalogueEntry> {
+    state
+        .tool_catalogue
+        .tools
+        .get(procedure)
+        .map(|entry| entry.clone())
+        .filter(|entry| !is_expired(entry, state))
+}
+
+/// Returns the procedures of the available tools, sorted by name.
+pub fn get_tools(state: &AppState) -> Vec<CatalogueEntry> {
+    let mut tools: Vec<CatalogueEntry> = state
+        .tool_catalogue
+        .tools
+        .iter()
+        .map(|entry| entry.clone())
+        .filter(|entry| !is_expired(entry, state))
+        .collect();
+
+    tools.sort_by(|a, b| a.procedure.cmp(&b.procedure));
+    tools
+}
+
+pub async fn run_rabbit_mq_control_read_loop(mut consumer: RabbitMqConsumer, state: AppState) {
+    loop {
+        let message = consumer.next_message::<AnnouncementMessage>().await;
+        let Ok(message) = message else {
+            error!("Failed to receive control message: {:?}", message);
+            continue;
+        };
+
+        info!(
+            microservice = message.microservice,
+            tools = message.tools.len(),
+            "Received tool announcement"
+        );
+
+        state.tool_catalogue.announce(message);
+    }
+}
+
+#[debug_handler]
+async fn get_catalogue(
+    _user: AccessTokenClaims,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse> {
+    Ok(Json(get_tools(&state)))
+}
+
+#[debug_handler]
+async fn get_catalogue_entry(
+    Path(procedure): Path<String>,
+    _user: AccessTokenClaims,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse> {
+    let tool = get_tool(&procedure, &state).ok_or(AppError::EntityNotFound)?;
+    Ok(Json(tool))
+}
@@ -0,0 +1,205 @@
+use crate::error::{AppError, Result};
+use crate::AppState;
+use ::image::{ImageDecoder, ImageReader};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+use std::path::Path;
+
+/// A condition a tool only runs under, evaluated against the image the tool takes as input.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolCondition {
+    /// The predicate the input image must match for the tool to run.
+    pub when: Predicate,
+    /// The amount of following tools to skip too when the predicate doesn't match,
+    /// e.g. the tools that only make sense after this one.
+    #[serde(default)]
+    pub skip_following: u32,
+}
+
+/// A predicate over the properties of an image, e.g.
+/// `{"property": "width", "operator": ">", "value": 2000}` or
+/// `{"any": [{"property": "orientation", "operator": "!=", "value": 1}, ...]}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Predicate {
+    All {
+        all: Vec<Predicate>,
+    },
+    Any {
+        any: Vec<Predicate>,
+    },
+    Not {
+        not: Box<Predicate>,
+    },
+    Compare {
+        property: ImageProperty,
+        operator: Operator,
+        value: f64,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageProperty {
+    Width,
+    Height,
+    /// The EXIF orientation of the image, 1 if it has none.
+    Orientation,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Operator {
+    #[serde(rename = "==")]
+    Equal,
+    #[serde(rename = "!=")]
+    NotEqual,
+    #[serde(rename = ">")]
+    Greater,
+    #[serde(rename = ">=")]
+    GreaterOrEqual,
+    #[serde(rename = "<")]
+    Less,
+    #[serde(rename = "<=")]
+    LessOrEqual,
+}
+
+impl Predicate {
+    pub fn matches(&self, metadata: &ImageMetadata) -> bool {
+        match self {
+            Predicate::All { all } => all.iter().all(|predicate| predicate.matches(metadata)),
+            Predicate::Any { any } => any.iter().any(|predicate| predicate.matches(metadata)),
+            Predicate::Not { not } => !not.matches(metadata),
+            Predicate::Compare {
+                property,
+                operator,
+                value,
+            } => {
+                let actual = metadata.get(*property);
+                match operator {
+                    Operator::Equal => actual == *value,
+                    Operator::NotEqual => actual != *value,
+                    Operator::Greater => actual > *value,
+                    Operator::GreaterOrEqual => actual >= *value,
+                    Operator::Less => actual < *value,
+                    Operator::LessOrEqual => actual <= *value,
+                }
+            }
+        }
+    }
+}
+
+/// The properties of an image conditions are evaluated against.
+#[derive(Debug, Clone, Copy)]
+pub struct ImageMetadata {
+    pub width: u32,
+    pub height: u32,
+    pub orientation: u8,
+}
+
+impl ImageMetadata {
+    /// Reads the metadata of an image from its header, without decoding it.
+    pub async fn read(path: &Path, state: &AppState) -> Result<Self> {
+        let image_bytes = state.storage.read(path).await?;
+
+        tokio::task::spawn_blocking(move || {
+            let mut decoder = ImageReader::new(Cursor::new(image_bytes))
+                .with_guessed_format()?
+                .into_decoder()?;
+
+            let (width, height) = decoder.dimensions();
+            let orientation = decoder
+                .orientation()
+                .map(|orientation| orientation.to_exif())
+                .unwrap_or(1);
+
+            Ok(Self {
+                width,
+                height,
+                orientation,
+            })
+        })
+        .await
+        .map_err(|_| AppError::InternalError)?
+    }
+
+    /// Scales the size of the image, e.g. to evaluate conditions on a proxy as if it was the
+    /// full size image.
+    pub fn scale(self, factor: f64) -> Self {
+        Self {
+            width: (self.width as f64 * factor).round() as u32,
+            height: (self.height as f64 * factor).round() as u32,
+            ..self
+        }
+    }
+
+    fn get(&self, property: ImageProperty) -> f64 {
+        match property {
+            ImageProperty::Width => self.width.into(),
+            ImageProperty::Height => self.height.into(),
+            ImageProperty::Orientation => self.orientation.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tool::condition::{ImageMetadata, Predicate};
+    use serde_json::json;
+
+    const METADATA: ImageMetadata = ImageMetadata {
+        width: 3000,
+        height: 2000,
+        orientation: 6,
+    };
+
+    fn predicate(value: serde_json::Value) -> Predicate {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_compare() {
+        let wide = predicate(json!({"property": "width", "operator": ">", "value": 2000}));
+        assert!(wide.matches(&METADATA));
+
+        let tall = predicate(json!({"property": "height", "operator": ">=", "value": 2001}));
+        assert!(!tall.matches(&METADATA));
+
+        let rotated = predicate(json!({"property": "orientation", "operator": "!=", "value": 1}));
+        assert!(rotated.matches(&METADATA));
+    }
+
+    #[test]
+    fn test_combinators() {
+        let all = predicate(json!({"all": [
+            {"property": "width", "operator": "==", "value": 3000},
+            {"property": "height", "operator": "<", "value": 1000},
+        ]}));
+        assert!(!all.matches(&METADATA));
+
+        let any = predicate(json!({"any": [
+            {"property": "width", "operator": "==", "value": 3000},
+            {"property": "height", "operator": "<", "value": 1000},
+        ]}));
+        assert!(any.matches(&METADATA));
+
+        let not = predicate(json!({"not": {"property": "width", "operator": "<=", "value": 3000}}));
+        assert!(!not.matches(&METADATA));
+
+        assert!(predicate(json!({"all": []})).matches(&METADATA));
+        assert!(!predicate(json!({"any": []})).matches(&METADATA));
+    }
+
+    #[test]
+    fn test_scale() {
+        let proxy = ImageMetadata {
+            width: 1024,
+            height: 683,
+            orientation: 6,
+        };
+
+        let scaled = proxy.scale(3000.0 / 1024.0);
+        assert_eq!(scaled.width, 3000);
+        assert_eq!(scaled.height, 2001);
+        assert_eq!(scaled.orientation, 6);
+    }
+}
@@ -1,19 +1,25 @@
-use crate::error::Result;
+use crate::cleanup::Cleanup;
+use crate::error::{AppError, Result};
 use crate::image::model::Image;
-use crate::tool::model::{ImageVersion, RequestedTool, Tool};
-use crate::tool::queue;
-use crate::tool::queue::QueuedImageApplyTool;
-use crate::{config, AppState};
+use crate::job::model::{Job, JobStep, JobStepStatus};
+use crate::tool::export::ExportSelection;
+use crate::tool::model::{
+    ImageVersion, PipelineDocument, PipelineTool, PreviewVersion, RequestedTool, Tool, ToolGraph,
+};
+use crate::tool::{export, queue, validation};
+use crate::{config, image, job, signed_url, AppState};
+use axum::body::Body;
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
-use std::io::Write;
-use tracing::{debug, info};
+use sqlx::PgConnection;
+use std::collections::{HashMap, HashSet};
+use tracing::info;
 use uuid::Uuid;
 
 pub async fn get_applied_tools(project_uuid: Uuid, state: &AppState) -> Result<Vec<Tool>> {
     let tools = sqlx::query_as!(
         Tool,
-        "SELECT id, position, procedure, parameters, project_id FROM tools WHERE project_id = $1 ORDER BY position ASC",
+        "SELECT id, position, parent_id, procedure, parameters, condition, enabled, project_id, hash FROM tools WHERE project_id = $1 ORDER BY position ASC",
         project_uuid
     )
         .fetch_all(&state.db_pool)
@@ -22,22 +28,32 @@ pub async fn get_applied_tools(project_uuid: Uuid, state: &AppState) -> Result<V
     Ok(tools)
 }
 
-async fn get_last_applied_tool(project_uuid: Uuid, state: &AppState) -> Result<Option<Tool>> {
-    let tool = sqlx::query_as!(
+/// Locks the tools of a project until the transaction ends, so concurrent changes to them are
+/// made one after the other, and returns them.
+async fn lock_tools(project_uuid: Uuid, transaction: &mut PgConnection) -> Result<Vec<Tool>> {
+    sqlx::query!(
+        "SELECT id FROM projects WHERE id = $1 FOR UPDATE",
+        project_uuid
+    )
+    .fetch_optional(&mut *transaction)
+    .await?
+    .ok_or(AppError::EntityNotFound)?;
+
+    let tools = sqlx::query_as!(
         Tool,
-        "SELECT id, position, procedure, parameters, project_id FROM tools WHERE project_id = $1 ORDER BY position DESC LIMIT 1",
+        "SELECT id, position, parent_id, procedure, parameters, condition, enabled, project_id, hash FROM tools WHERE project_id = $1 ORDER BY position ASC",
         project_uuid
     )
-        .fetch_optional(&state.db_pool)
+        .fetch_all(&mut *transaction)
         .await?;
 
-    Ok(tool)
+    Ok(tools)
 }
 
 pub async fn get_image_versions(project_id: Uuid, state: &AppState) -> Result<Vec<ImageVersion>> {
     let images = sqlx::query_as!(
         ImageVersion,
-        "SELECT id, original_image_id, project_id, tool_id, text_result, created_at FROM image_versions WHERE project_id = $1",
+        "SELECT id, original_image_id, project_id, tool_id, text_result, skipped, created_at, cache_key FROM image_versions WHERE project_id = $1",
         project_id
     )
         .fetch_all(&state.db_pool)
@@ -46,61 +62,536 @@ pub async fn get_image_versions(project_id: Uuid, state: &AppState) -> Result<Ve
     Ok(images)
 }
 
+/// Locks the image versions of a project until the transaction ends, so they are deleted along
+/// with the other changes of the transaction, and returns them.
+pub async fn lock_image_versions(
+    project_id: Uuid,
+    transaction: &mut PgConnection,
+) -> Result<Vec<ImageVersion>> {
+    let images = sqlx::query_as!(
+        ImageVersion,
+        "SELECT id, original_image_id, project_id, tool_id, text_result, skipped, created_at, cache_key FROM image_versions WHERE project_id = $1 FOR UPDATE",
+        project_id
+    )
+        .fetch_all(&mut *transaction)
+        .await?;
+
+    Ok(images)
+}
+
+pub async fn get_preview_versions(
+    project_id: Uuid,
+    state: &AppState,
+) -> Result<Vec<PreviewVersion>> {
+    let previews = sqlx::query_as!(
+        PreviewVersion,
+        "SELECT id, original_image_id, project_id, tool_id, text_result, skipped, created_at FROM preview_versions WHERE project_id = $1",
+        project_id
+    )
+        .fetch_all(&state.db_pool)
+        .await?;
+
+    Ok(previews)
+}
+
+pub async fn get_tool_graph(project_uuid: Uuid, state: &AppState) -> Result<ToolGraph> {
+    let tools = get_applied_tools(project_uuid, state).await?;
+
+    // the edges are only changed after checking they don't form a cycle
+    ToolGraph::new(tools).ok_or(AppError::InternalError)
+}
+
+/// Adds a tool taking the output of the given parent tool as input, or of the last tool of the
+/// project if none is given.
 pub async fn add_tool(
     project_uuid: Uuid,
     requested_tool: RequestedTool,
+    parent_uuid: Option<Uuid>,
     state: &AppState,
 ) -> Result<Tool> {
     info!(
         tool = ?requested_tool,
+        parent = ?parent_uuid,
         "Adding tool to project: {}", project_uuid
     );
-    let last_applied_tool = get_last_applied_tool(project_uuid, state).await?;
+    validation::validate_tool(&requested_tool, state)?;
+
+    // the position is taken from the last tool, so no other tool can be added in the meantime
+    let mut transaction = state.db_pool.begin().await?;
+    let mut tools = lock_tools(project_uuid, &mut transaction).await?;
+    let parent_uuid = parent_uuid.or_else(|| last_tool(&tools).map(|tool| tool.id));
+    let tool = insert_tool(
+        project_uuid,
+        requested_tool,
+        parent_uuid,
+        &mut tools,
+        &mut transaction,
+    )
+    .await?;
+    transaction.commit().await?;
+
+    Ok(tool)
+}
+
+/// Appends tools after the last tool of the project, each taking the output of the appended tool
+/// at the given index as input, or the output of the last tool of the project if none is given.
+/// Either all of them are added or none is.
+pub async fn append_tools(
+    project_uuid: Uuid,
+    requested_tools: Vec<PipelineTool>,
+    state: &AppState,
+) -> Result<Vec<Tool>> {
+    info!(
+        tools = requested_tools.len(),
+        "Appending tools to project: {}", project_uuid
+    );
+    validation::validate_tools(requested_tools.iter().map(|tool| &tool.tool), state)?;
+
+    let mut transaction = state.db_pool.begin().await?;
+    let mut tools = lock_tools(project_uuid, &mut transaction).await?;
+    let last_tool_uuid = last_tool(&tools).map(|tool| tool.id);
+
+    let mut added_tools: Vec<Tool> = Vec::with_capacity(requested_tools.len());
+    for (index, requested_tool) in requested_tools.into_iter().enumerate() {
+        let parent_uuid = match requested_tool.parent {
+            None => last_tool_uuid,
+            Some(parent_index) if parent_index < index => Some(added_tools[parent_index].id),
+            Some(_) => {
+                return Err(AppError::InvalidPipeline(
+                    "a tool can only take the output of a tool before it",
+                ))
+            }
+        };
+
+        let tool = insert_tool(
+            project_uuid,
+            requested_tool.tool,
+            parent_uuid,
+            &mut tools,
+            &mut transaction,
+        )
+        .await?;
+        added_tools.push(tool);
+    }
+    transaction.commit().await?;
 
-    let last_position = match &last_applied_tool {
+    Ok(added_tools)
+}
+
+/// Returns the tool applied last, the one new tools take the output of by default.
+fn last_tool(tools: &[Tool]) -> Option<&Tool> {
+    tools.iter().max_by_key(|tool| tool.position)
+}
+
+/// Inserts a tool after the last one of `tools`, the locked tools of the project, to which it
+/// is then added. The tool takes the output of the given parent, or the original image if none
+/// is given.
+async fn insert_tool(
+    project_uuid: Uuid,
+    requested_tool: RequestedTool,
+    parent_uuid: Option<Uuid>,
+    tools: &mut Vec<Tool>,
+    transaction: &mut PgConnection,
+) -> Result<Tool> {
+    let last_position = match last_tool(tools) {
         None => 0,
         Some(last_applied_tool) => last_applied_tool.position,
     };
 
+    let parent_tool = match parent_uuid {
+        None => None,
+        Some(parent_uuid) => Some(tools.iter().find(|tool| tool.id == parent_uuid).ok_or(
+            AppError::InvalidPipeline("the parent tool isn't in the project"),
+        )?),
+    };
+
+    let parameters = serde_json::to_value(requested_tool.parameters.clone()).unwrap();
+    let condition = requested_tool
+        .condition
+        .as_ref()
+        .map(serde_json::to_value)
+        .transpose()?;
+    let upstream_hash = parent_tool.and_then(|parent_tool| parent_tool.hash.as_deref());
+    let hash = Tool::hash(
+        &requested_tool.procedure,
+        &parameters,
+        condition.as_ref(),
+        requested_tool.enabled,
+        upstream_hash,
+    );
+
     let tool = Tool {
         id: Uuid::new_v4(),
         project_id: project_uuid,
         position: last_position + 1,
+        parent_id: parent_tool.map(|parent_tool| parent_tool.id),
         procedure: requested_tool.procedure.clone(),
-        parameters: serde_json::to_value(requested_tool.parameters.clone()).unwrap(),
+        parameters,
+        condition,
+        enabled: requested_tool.enabled,
+        hash: Some(hash),
     };
 
     sqlx::query!(
-        "INSERT INTO tools (id, project_id, position, procedure, parameters) VALUES ($1, $2, $3, $4, $5)",
+        "INSERT INTO tools (id, project_id, position, parent_id, procedure, parameters, condition, enabled, hash) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
         tool.id,
         tool.project_id,
         tool.position,
+        tool.parent_id,
         tool.procedure,
-        tool.parameters
+        tool.parameters,
+        tool.condition,
+        tool.enabled,
+        tool.hash
     )
-        .execute(&state.db_pool)
+        .execute(&mut *transaction)
         .await?;
 
+    tools.push(tool.clone());
+
     Ok(tool)
 }
 
-async fn delete_image_versions(project_uuid: Uuid, state: &AppState) -> Result<()> {
-    let delete_files = tokio::fs::remove_dir_all(config::generate_image_version_folder_uri(
-        project_uuid,
-        state,
-    ));
+/// Moves a tool, along with the tools that depend on it, to take the output of another tool as
+/// input, or the original image if no parent is given.
+/// The image versions of the moved tools are deleted, as their inputs changed.
+pub async fn set_tool_parent(
+    project_uuid: Uuid,
+    tool_uuid: Uuid,
+    parent_uuid: Option<Uuid>,
+    state: &AppState,
+) -> Result<ToolGraph> {
+    let mut transaction = state.db_pool.begin().await?;
+    let current_tools = lock_tools(project_uuid, &mut transaction).await?;
+    let graph = ToolGraph::new(current_tools.clone()).ok_or(AppError::InternalError)?;
+
+    if !graph.contains(tool_uuid) {
+        return Err(AppError::EntityNotFound);
+    }
+
+    if let Some(parent_uuid) = parent_uuid {
+        if !graph.contains(parent_uuid) {
+            return Err(AppError::InvalidPipeline(
+                "the parent tool isn't in the project",
+            ));
+        }
+
+        if graph.descendants(tool_uuid).contains(&parent_uuid) {
+            return Err(AppError::InvalidPipeline(
+                "a tool can't take the output of its own descendants",
+            ));
+        }
+    }
+
+    info!(
+        tool = ?tool_uuid,
+        parent = ?parent_uuid,
+        "Moving tool of project: {}", project_uuid
+    );
+
+    let mut tools = graph.nodes;
+    for tool in &mut tools {
+        if tool.id == tool_uuid {
+            tool.parent_id = parent_uuid;
+        }
+    }
+
+    let mut graph = ToolGraph::new(tools).ok_or(AppError::InternalError)?;
+    graph.rehash();
+
+    let cleanup = save_tool_graph(project_uuid, &current_tools, &graph, &mut transaction).await?;
+    transaction.commit().await?;
+    cleanup.run(state).await?;
+
+    Ok(graph)
+}
+
+/// Merges the given parameters over the parameters of a tool, and enables or disables it if
+/// asked to.
+/// The image versions of the tool and of the tools that depend on it are deleted, as their
+/// outputs changed.
+pub async fn update_tool(
+    project_uuid: Uuid,
+    tool_uuid: Uuid,
+    parameters: HashMap<String, serde_json::Value>,
+    enabled: Option<bool>,
+    state: &AppState,
+) -> Result<Tool> {
+    let mut transaction = state.db_pool.begin().await?;
+    let current_tools = lock_tools(project_uuid, &mut transaction).await?;
+
+    let mut tools = current_tools.clone();
+    let tool = tools
+        .iter_mut()
+        .find(|tool| tool.id == tool_uuid)
+        .ok_or(AppError::EntityNotFound)?;
+
+    let mut requested_tool: RequestedTool = tool.clone().try_into()?;
+    requested_tool.parameters.extend(parameters);
+    validation::validate_tool(&requested_tool, state)?;
+
+    info!(
+        tool = ?tool_uuid,
+        parameters = ?requested_tool.parameters,
+        enabled,
+        "Updating tool of project: {}", project_uuid
+    );
+    tool.parameters = serde_json::to_value(requested_tool.parameters)?;
+    tool.enabled = enabled.unwrap_or(tool.enabled);
+
+    let mut graph = ToolGraph::new(tools).ok_or(AppError::InternalError)?;
+    graph.rehash();
+
+    let cleanup = save_tool_graph(project_uuid, &current_tools, &graph, &mut transaction).await?;
+    transaction.commit().await?;
+    cleanup.run(state).await?;
+
+    let tool = graph
+        .nodes
+        .into_iter()
+        .find(|tool| tool.id == tool_uuid)
+        .ok_or(AppError::InternalError)?;
+
+    Ok(tool)
+}
+
+/// Deletes a tool, the tools that took its output as input taking the input of the deleted tool
+/// instead.
+pub async fn delete_tool(
+    project_uuid: Uuid,
+    tool_uuid: Uuid,
+    state: &AppState,
+) -> Result<ToolGraph> {
+    let mut transaction = state.db_pool.begin().await?;
+    let current_tools = lock_tools(project_uuid, &mut transaction).await?;
+
+    let deleted_tool = current_tools
+        .iter()
+        .find(|tool| tool.id == tool_uuid)
+        .ok_or(AppError::EntityNotFound)?;
+
+    info!(tool = ?tool_uuid, "Deleting tool of project: {}", project_uuid);
+
+    let mut tools: Vec<Tool> = current_tools
+        .iter()
+        .filter(|tool| tool.id != tool_uuid)
+        .cloned()
+        .collect();
+    for tool in &mut tools {
+        if tool.parent_id == Some(tool_uuid) {
+            tool.parent_id = deleted_tool.parent_id;
+        }
+        if tool.position > deleted_tool.position {
+            tool.position -= 1;
+        }
+    }
+
+    let mut graph = ToolGraph::new(tools).ok_or(AppError::InternalError)?;
+    graph.rehash();
+
+    job::controller::supersede_jobs(project_uuid, &mut transaction).await?;
+    let mut cleanup = delete_preview_versions(project_uuid, &mut transaction).await?;
+
+    let deleted_image_versions = lock_image_versions(project_uuid, &mut transaction)
+        .await?
+        .into_iter()
+        .filter(|image_version| image_version.tool_id == tool_uuid)
+        .collect();
+    cleanup.extend(delete_image_versions(deleted_image_versions, &mut transaction).await?);
+
+    // the children are moved first, as deleting a tool deletes the tools that depend on it
+    cleanup.extend(save_tool_graph(project_uuid, &current_tools, &graph, &mut transaction).await?);
+
+    sqlx::query!("DELETE FROM tools WHERE id = $1", tool_uuid)
+        .execute(&mut *transaction)
+        .await?;
+
+    transaction.commit().await?;
+    cleanup.run(state).await?;
+
+    Ok(graph)
+}
+
+/// Moves a tool to the given position (1 being the first tool), shifting the tools in between.
+/// If each tool takes the output of the one before it, the tools are chained again in their new
+/// order, otherwise the order of the tools only changes the order of the branches.
+pub async fn move_tool(
+    project_uuid: Uuid,
+    tool_uuid: Uuid,
+    position: i32,
+    state: &AppState,
+) -> Result<ToolGraph> {
+    let mut transaction = state.db_pool.begin().await?;
+    let current_tools = lock_tools(project_uuid, &mut transaction).await?;
+
+    let mut tools = current_tools.clone();
+    let is_chain = tools
+        .iter()
+        .enumerate()
+        .all(|(index, tool)| tool.parent_id == index.checked_sub(1).map(|parent| tools[parent].id));
+
+    let index = tools
+        .iter()
+        .position(|tool| tool.id == tool_uuid)
+        .ok_or(AppError::EntityNotFound)?;
+    let tool = tools.remove(index);
+    let new_index = (position.max(1) as usize - 1).min(tools.len());
+    tools.insert(new_index, tool);
+
+    info!(
+        tool = ?tool_uuid,
+        position = new_index + 1,
+        chain = is_chain,
+        "Moving tool of project: {}", project_uuid
+    );
+
+    let mut parent_id = None;
+    for (index, tool) in tools.iter_mut().enumerate() {
+        tool.position = index as i32 + 1;
+        if is_chain {
+            tool.parent_id = parent_id;
+            parent_id = Some(tool.id);
+        }
+    }
+
+    let mut graph = ToolGraph::new(tools).ok_or(AppError::InternalError)?;
+    graph.rehash();
+
+    let cleanup = save_tool_graph(project_uuid, &current_tools, &graph, &mut transaction).await?;
+    transaction.commit().await?;
+    cleanup.run(state).await?;
+
+    Ok(graph)
+}
+
+/// Saves the tools of a graph that changed from the current ones.
+/// The image versions of the tools whose hash changed are deleted, as their outputs changed.
+async fn save_tool_graph(
+    project_uuid: Uuid,
+    current_tools: &[Tool],
+    graph: &ToolGraph,
+    transaction: &mut PgConnection,
+) -> Result<Cleanup> {
+    let current_tools: HashMap<Uuid, &Tool> =
+        current_tools.iter().map(|tool| (tool.id, tool)).collect();
+
+    let changed_tools: Vec<&Tool> = graph
+        .nodes
+        .iter()
+        .filter(|tool| {
+            current_tools.get(&tool.id).is_none_or(|current| {
+                current.position != tool.position
+                    || current.parent_id != tool.parent_id
+                    || current.hash != tool.hash
+            })
+        })
+        .collect();
+    let rehashed_tool_ids: Vec<Uuid> = changed_tools
+        .iter()
+        .filter(|tool| current_tools.get(&tool.id).map(|current| &current.hash) != Some(&tool.hash))
+        .map(|tool| tool.id)
+        .collect();
+
+    let mut cleanup = Cleanup::default();
+    if !rehashed_tool_ids.is_empty() {
+        job::controller::supersede_jobs(project_uuid, transaction).await?;
+        cleanup.extend(delete_preview_versions(project_uuid, transaction).await?);
+
+        let rehashed_image_versions = lock_image_versions(project_uuid, transaction)
+            .await?
+            .into_iter()
+            .filter(|image_version| rehashed_tool_ids.contains(&image_version.tool_id))
+            .collect();
+        cleanup.extend(delete_image_versions(rehashed_image_versions, transaction).await?);
+    }
+
+    for tool in changed_tools {
+        sqlx::query!(
+            "UPDATE tools SET position = $2, parent_id = $3, parameters = $4, enabled = $5, hash = $6 WHERE id = $1",
+            tool.id,
+            tool.position,
+            tool.parent_id,
+            tool.parameters,
+            tool.enabled,
+            tool.hash
+        )
+        .execute(&mut *transaction)
+        .await?;
+    }
+
+    Ok(cleanup)
+}
+
+/// Deletes the rows of image versions, returning their files to be removed once the
+/// transaction commits.
+pub async fn delete_image_versions(
+    image_versions: Vec<ImageVersion>,
+    transaction: &mut PgConnection,
+) -> Result<Cleanup> {
+    let ids: Vec<Uuid> = image_versions
+        .iter()
+        .map(|image_version| image_version.id)
+        .collect();
+
+    sqlx::query!("DELETE FROM image_versions WHERE id = ANY($1)", &ids)
+        .execute(transaction)
+        .await?;
+
+    let mut cleanup = Cleanup::default();
+    for image_version in image_versions {
+        match image_version.cache_key {
+            // other image versions may still be using the file
+            Some(cache_key) => cleanup.release_cached_result(cache_key),
+            None => cleanup.remove_file(image_version.get_uri()),
+        }
+    }
+
+    Ok(cleanup)
+}
 
-    let delete_sql = sqlx::query!(
-        "DELETE FROM image_versions WHERE project_id = $1",
+/// Deletes the rows of the preview versions of a project, returning their files to be removed
+/// once the transaction commits.
+async fn delete_preview_versions(
+    project_uuid: Uuid,
+    transaction: &mut PgConnection,
+) -> Result<Cleanup> {
+    sqlx::query!(
+        "DELETE FROM preview_versions WHERE project_id = $1",
         project_uuid
     )
-    .execute(&state.db_pool);
+    .execute(transaction)
+    .await?;
 
-    let (_delete_files, delete_sql) = tokio::join!(delete_files, delete_sql);
+    let mut cleanup = Cleanup::default();
+    cleanup.remove_folder(config::generate_preview_folder_uri(project_uuid));
 
-    delete_sql?;
+    Ok(cleanup)
+}
 
-    Ok(())
+/// Deletes the rows of the preview versions of some images of a project, returning their files
+/// to be removed once the transaction commits.
+async fn delete_image_preview_versions(
+    project_uuid: Uuid,
+    images: &[Image],
+    transaction: &mut PgConnection,
+) -> Result<Cleanup> {
+    let image_ids: Vec<Uuid> = images.iter().map(|image| image.id).collect();
+    sqlx::query!(
+        "DELETE FROM preview_versions WHERE project_id = $1 AND original_image_id = ANY($2)",
+        project_uuid,
+        &image_ids
+    )
+    .execute(transaction)
+    .await?;
+
+    let mut cleanup = Cleanup::default();
+    for image in images {
+        cleanup.remove_folder(
+            config::generate_preview_folder_uri(project_uuid).join(image.id.to_string()),
+        );
+    }
+
+    Ok(cleanup)
 }
 
 pub async fn update_tools(
@@ -108,71 +599,388 @@ pub async fn update_tools(
     tools: Vec<RequestedTool>,
     state: &AppState,
 ) -> Result<Vec<Tool>> {
-    delete_image_versions(project_uuid, state).await?;
+    // each tool takes the output of the one before it
+    let tools = tools
+        .into_iter()
+        .enumerate()
+        .map(|(index, tool)| (tool, index.checked_sub(1)))
+        .collect();
 
-    sqlx::query!("DELETE FROM tools WHERE project_id = $1", project_uuid)
-        .execute(&state.db_pool)
-        .await?;
+    set_tools(project_uuid, tools, state).await
+}
+
+/// Replaces the tools of a project, each tool taking the output of the tool at the given index
+/// as input, or the original image if none is given.
+pub async fn set_tools(
+    project_uuid: Uuid,
+    tools: Vec<(RequestedTool, Option<usize>)>,
+    state: &AppState,
+) -> Result<Vec<Tool>> {
+    validation::validate_tools(tools.iter().map(|(tool, _)| tool), state)?;
 
-    let mut result = vec![];
+    // the positions are only unique once every tool is rewritten
+    let mut transaction = state.db_pool.begin().await?;
+    let current_tools = lock_tools(project_uuid, &mut transaction).await?;
+
+    let mut new_tools: Vec<Tool> = vec![];
+
+    for (index, (requested_tool, parent_index)) in tools.into_iter().enumerate() {
+        let parent = match parent_index {
+            None => None,
+            Some(parent_index) if parent_index < index => Some(&new_tools[parent_index]),
+            Some(_) => {
+                return Err(AppError::InvalidPipeline(
+                    "a tool can only take the output of a tool before it",
+                ))
+            }
+        };
+
+        let parameters = serde_json::to_value(requested_tool.parameters)?;
+        let condition = requested_tool
+            .condition
+            .map(serde_json::to_value)
+            .transpose()?;
+        let hash = Tool::hash(
+            &requested_tool.procedure,
+            &parameters,
+            condition.as_ref(),
+            requested_tool.enabled,
+            parent.and_then(|parent| parent.hash.as_deref()),
+        );
 
-    for (position, requested_tool) in tools.into_iter().enumerate() {
-        let tool = Tool {
+        new_tools.push(Tool {
             id: Uuid::new_v4(),
             project_id: project_uuid,
-            position: position as i32 + 1,
-            procedure: requested_tool.procedure.clone(),
-            parameters: serde_json::to_value(requested_tool.parameters.clone()).unwrap(),
-        };
+            position: index as i32 + 1,
+            parent_id: parent.map(|parent| parent.id),
+            procedure: requested_tool.procedure,
+            parameters,
+            condition,
+            enabled: requested_tool.enabled,
+            hash: Some(hash),
+        });
+    }
+
+    // the hashes are chained, so a tool with the same hash as a current one has the same
+    // ancestors too, it keeps the id and image versions of the current one
+    let mut current_by_hash: HashMap<String, Uuid> = HashMap::new();
+    for tool in &current_tools {
+        if let Some(hash) = &tool.hash {
+            current_by_hash.entry(hash.clone()).or_insert(tool.id);
+        }
+    }
+
+    let mut kept_ids: HashMap<Uuid, Uuid> = HashMap::new(); // new tool id -> current tool id
+    for tool in &mut new_tools {
+        if let Some(parent_id) = tool.parent_id {
+            tool.parent_id = Some(kept_ids.get(&parent_id).copied().unwrap_or(parent_id));
+        }
+
+        if let Some(current_id) = tool
+            .hash
+            .as_ref()
+            .and_then(|hash| current_by_hash.remove(hash))
+        {
+            kept_ids.insert(tool.id, current_id);
+            tool.id = current_id;
+        }
+    }
+
+    info!(
+        unchanged = kept_ids.len(),
+        changed = new_tools.len() - kept_ids.len(),
+        "Updating tools of project: {}",
+        project_uuid
+    );
+
+    job::controller::supersede_jobs(project_uuid, &mut transaction).await?;
+    let mut cleanup = delete_preview_versions(project_uuid, &mut transaction).await?;
+
+    let kept_tools: HashSet<Uuid> = kept_ids.into_values().collect();
+    let removed_tools: Vec<Uuid> = current_tools
+        .iter()
+        .map(|tool| tool.id)
+        .filter(|tool_id| !kept_tools.contains(tool_id))
+        .collect();
+
+    let removed_image_versions = lock_image_versions(project_uuid, &mut transaction)
+        .await?
+        .into_iter()
+        .filter(|image_version| removed_tools.contains(&image_version.tool_id))
+        .collect();
+    cleanup.extend(delete_image_versions(removed_image_versions, &mut transaction).await?);
+
+    for tool in &new_tools {
+        if kept_tools.contains(&tool.id) {
+            sqlx::query!(
+                "UPDATE tools SET position = $2, parent_id = $3 WHERE id = $1",
+                tool.id,
+                tool.position,
+                tool.parent_id
+            )
+            .execute(&mut *transaction)
+            .await?;
+            continue;
+        }
 
         sqlx::query!(
-            "INSERT INTO tools (id, project_id, position, procedure, parameters) VALUES ($1, $2, $3, $4, $5)",
+            "INSERT INTO tools (id, project_id, position, parent_id, procedure, parameters, condition, enabled, hash) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
             tool.id,
             tool.project_id,
             tool.position,
+            tool.parent_id,
             tool.procedure,
-            tool.parameters
+            tool.parameters,
+            tool.condition,
+            tool.enabled,
+            tool.hash
         )
-            .execute(&state.db_pool)
+            .execute(&mut *transaction)
             .await?;
+    }
+
+    sqlx::query!("DELETE FROM tools WHERE id = ANY($1)", &removed_tools)
+        .execute(&mut *transaction)
+        .await?;
+
+    transaction.commit().await?;
+    cleanup.run(state).await?;
+
+    Ok(new_tools)
+}
+
+pub async fn export_pipeline(project_uuid: Uuid, state: &AppState) -> Result<PipelineDocument> {
+    let graph = get_tool_graph(project_uuid, state).await?;
 
-        result.push(tool);
+    let indexes: HashMap<Uuid, usize> = graph
+        .nodes
+        .iter()
+        .enumerate()
+        .map(|(index, tool)| (tool.id, index))
+        .collect();
+
+    let tools = graph
+        .nodes
+        .into_iter()
+        .map(|tool| {
+            let parent = tool.parent_id.map(|parent_id| indexes[&parent_id]);
+            Ok(PipelineTool {
+                tool: tool.try_into()?,
+                parent,
+            })
+        })
+        .collect::<std::result::Result<Vec<_>, serde_json::Error>>()?;
+
+    Ok(PipelineDocument::new(tools)?)
+}
+
+/// Replaces the tools of a project with the ones of a document, after checking the document is
+/// intact.
+pub async fn import_pipeline(
+    project_uuid: Uuid,
+    document: PipelineDocument,
+    state: &AppState,
+) -> Result<Vec<Tool>> {
+    if document.schema_version != PipelineDocument::SCHEMA_VERSION {
+        return Err(AppError::InvalidPipelineDocument(format!(
+            "unsupported schema version {}",
+            document.schema_version
+        )));
+    }
+
+    if PipelineDocument::checksum(&document.tools)? != document.checksum {
+        return Err(AppError::InvalidPipelineDocument(
+            "the checksum doesn't match the tools".to_string(),
+        ));
     }
 
-    Ok(result)
+    info!(
+        tools = document.tools.len(),
+        "Importing pipeline into project: {}", project_uuid
+    );
+
+    let tools = document
+        .tools
+        .into_iter()
+        .map(|tool| (tool.tool, tool.parent))
+        .collect();
+
+    set_tools(project_uuid, tools, state).await
 }
 
 pub async fn apply_added_tools(
     project_uuid: Uuid,
     user_uuid: Uuid,
+    priority: u8,
+    preview: bool,
     images: &[Image],
     state: &AppState,
-) -> Result<()> {
-    delete_image_versions(project_uuid, state).await?;
+) -> Result<Job> {
+    let graph = get_tool_graph(project_uuid, state).await?;
 
-    let tools = get_applied_tools(project_uuid, state).await;
-
-    let requested_tools: VecDeque<(Uuid, RequestedTool)> = tools?
+    // a tool that can't be converted would leave its descendants without an input
+    let requested_tools: Vec<(Uuid, Option<Uuid>, i32, RequestedTool)> = graph
+        .nodes
         .into_iter()
-        .filter_map(|tool| Some((tool.id, tool.try_into().ok()?)))
+        .map(|tool| Ok((tool.id, tool.parent_id, tool.position, tool.try_into()?)))
+        .collect::<std::result::Result<_, serde_json::Error>>()?;
+
+    // previews run on the proxies, so they can't reuse the image versions
+    let image_versions = if preview {
+        vec![]
+    } else {
+        get_image_versions(project_uuid, state).await?
+    };
+
+    let image_version_by_tool: HashMap<(Uuid, Uuid), Uuid> = image_versions
+        .iter()
+        .map(|image_version| {
+            (
+                (image_version.original_image_id, image_version.tool_id),
+                image_version.id,
+            )
+        })
         .collect();
 
+    let mut job = Job {
+        id: Uuid::new_v4(),
+        project_id: project_uuid,
+        user_id: user_uuid,
+        created_at: Utc::now(),
+        cancelled_at: None,
+        generation: 0, // assigned when the job is created
+        priority: priority.into(),
+        preview,
+    };
+
+    let mut steps = vec![];
+    let mut reused_image_versions = HashSet::new();
+
     for image in images {
-        let image_input_uri = image.get_uri(state);
-        let queued_image_apply_tool = QueuedImageApplyTool::new_generate_output_uri(
-            project_uuid,
-            user_uuid,
-            image.id,
-            image_input_uri,
-            requested_tools.clone(),
-            state,
-        );
+        // the result of each step is cached by the hash of the image and of the steps until it
+        let mut cache_keys: HashMap<Uuid, Option<String>> = HashMap::new();
+        // the output of each tool, either an image version reused from a previous job
+        // or the step that is going to compute it
+        let mut reused: HashMap<Uuid, Uuid> = HashMap::new();
+        let mut step_ids: HashMap<Uuid, Uuid> = HashMap::new();
+        // the tool whose output each disabled tool passes on, none for the original image
+        let mut bypassed: HashMap<Uuid, Option<Uuid>> = HashMap::new();
+
+        for (tool_uuid, parent_uuid, position, requested_tool) in &requested_tools {
+            let parent_uuid = &match parent_uuid {
+                Some(parent_uuid) => bypassed
+                    .get(parent_uuid)
+                    .copied()
+                    .unwrap_or(Some(*parent_uuid)),
+                None => None,
+            };
 
-        debug!(queued_image_apply_tool = ?queued_image_apply_tool, "Queued image apply tool");
-        queue::add_to_queue(queued_image_apply_tool, state).await?;
+            if !requested_tool.enabled {
+                bypassed.insert(*tool_uuid, *parent_uuid);
+                continue;
+            }
+
+            let upstream_cache_key = match parent_uuid {
+                None => image.content_hash.clone().filter(|_| !preview),
+                Some(parent_uuid) => match cache_keys.get(parent_uuid) {
+                    Some(cache_key) => cache_key.clone(),
+                    // the parent couldn't be applied
+                    None => continue,
+                },
+            };
+
+            let parameters = serde_json::to_value(&requested_tool.parameters)?;
+            let condition = requested_tool
+                .condition
+                .as_ref()
+                .map(serde_json::to_value)
+                .transpose()?;
+            let cache_key = upstream_cache_key.map(|key| {
+                Tool::hash(
+                    &requested_tool.procedure,
+                    &parameters,
+                    condition.as_ref(),
+                    true,
+                    Some(&key),
+                )
+            });
+            cache_keys.insert(*tool_uuid, cache_key.clone());
+
+            // the tools keep their ids while they and their ancestors don't change,
+            // so the image version of a tool is still valid if the one of its parent is too
+            let parent_reused =
+                parent_uuid.is_none_or(|parent_uuid| reused.contains_key(&parent_uuid));
+            if let Some(image_version_uuid) = image_version_by_tool
+                .get(&(image.id, *tool_uuid))
+                .filter(|_| parent_reused)
+            {
+                reused.insert(*tool_uuid, *image_version_uuid);
+                continue;
+            }
+
+            let step_uuid = Uuid::new_v4();
+            step_ids.insert(*tool_uuid, step_uuid);
+
+            steps.push(JobStep {
+                id: step_uuid,
+                job_id: job.id,
+                original_image_id: image.id,
+                tool_id: *tool_uuid,
+                position: *position,
+                procedure: requested_tool.procedure.clone(),
+                parameters,
+                condition,
+                status: JobStepStatus::Queued,
+                message_id: None,
+                started_at: None,
+                finished_at: None,
+                processing_time: None,
+                error_code: None,
+                error_message: None,
+                attempts: 0,
+                skipped: false,
+                parent_step_id: parent_uuid
+                    .and_then(|parent_uuid| step_ids.get(&parent_uuid).copied()),
+                input_version_id: parent_uuid
+                    .and_then(|parent_uuid| reused.get(&parent_uuid).copied()),
+                cache_key,
+            });
+        }
+
+        reused_image_versions.extend(reused.into_values());
     }
 
-    Ok(())
+    info!(
+        steps = steps.len(),
+        reused = reused_image_versions.len(),
+        "Applying tools of project: {}",
+        project_uuid
+    );
+
+    // results of the previous generations on these images are rejected from now on,
+    // so the versions that are going to be recomputed can be safely deleted along with it
+    let mut transaction = state.db_pool.begin().await?;
+    job::controller::create_job(&mut job, &steps, &mut transaction).await?;
+    let cleanup = if preview {
+        delete_image_preview_versions(project_uuid, images, &mut transaction).await?
+    } else {
+        let replaced_image_versions = image_versions
+            .into_iter()
+            .filter(|image_version| {
+                !reused_image_versions.contains(&image_version.id)
+                    && images
+                        .iter()
+                        .any(|image| image.id == image_version.original_image_id)
+            })
+            .collect();
+        delete_image_versions(replaced_image_versions, &mut transaction).await?
+    };
+    transaction.commit().await?;
+    cleanup.run(state).await?;
+
+    queue::schedule(state);
+
+    Ok(job)
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -188,75 +996,149 @@ impl ImageVersionWithUrl {
             "{}/api/v1/projects/{}/tools/images/{}",
             state.config.picturas_public_url, image_version.project_id, image_version.id
         );
+        let url = signed_url::sign_url(url, image_version.project_id, image_version.id, state);
         Self { url, image_version }
     }
 }
 
-pub async fn save_image_version(image_version: &ImageVersion, state: &AppState) -> Result<()> {
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PreviewVersionWithUrl {
+    #[serde(flatten)]
+    preview_version: PreviewVersion,
+    url: String,
+}
+
+impl PreviewVersionWithUrl {
+    pub fn from_preview_version(preview_version: PreviewVersion, state: &AppState) -> Self {
+        let url = format!(
+            "{}/api/v1/projects/{}/tools/previews/{}",
+            state.config.picturas_public_url, preview_version.project_id, preview_version.id
+        );
+        let url = signed_url::sign_url(url, preview_version.project_id, preview_version.id, state);
+        Self {
+            url,
+            preview_version,
+        }
+    }
+}
+
+pub async fn save_preview_version(
+    preview_version: &PreviewVersion,
+    transaction: &mut PgConnection,
+) -> Result<()> {
     sqlx::query!(
-        "INSERT INTO image_versions (id, original_image_id, project_id, tool_id, text_result, created_at) VALUES ($1, $2, $3, $4, $5, $6)",
+        "INSERT INTO preview_versions (id, original_image_id, project_id, tool_id, text_result, skipped, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        preview_version.id,
+        preview_version.original_image_id,
+        preview_version.project_id,
+        preview_version.tool_id,
+        preview_version.text_result,
+        preview_version.skipped,
+        preview_version.created_at
+    )
+        .execute(transaction)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn load_preview_version(
+    project_id: Uuid,
+    preview_version_uuid: Uuid,
+    state: &AppState,
+) -> Result<Vec<u8>> {
+    let preview_version = sqlx::query_as!(
+        PreviewVersion,
+        "SELECT id, original_image_id, project_id, tool_id, text_result, skipped, created_at FROM preview_versions WHERE project_id = $1 AND id = $2",
+        project_id,
+        preview_version_uuid
+    )
+        .fetch_one(&state.db_pool)
+        .await?;
+
+    let image_path = preview_version.get_uri();
+    let image_data = state.storage.read(&image_path).await?;
+
+    Ok(image_data)
+}
+
+pub async fn save_image_version(
+    image_version: &ImageVersion,
+    transaction: &mut PgConnection,
+) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO image_versions (id, original_image_id, project_id, tool_id, text_result, skipped, created_at, cache_key) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
         image_version.id,
         image_version.original_image_id,
         image_version.project_id,
         image_version.tool_id,
         image_version.text_result,
-        image_version.created_at
+        image_version.skipped,
+        image_version.created_at,
+        image_version.cache_key
     )
-        .execute(&state.db_pool)
+        .execute(transaction)
         .await?;
 
     Ok(())
 }
 
-pub async fn load_image_version(
-    project_id: Uuid,
+pub async fn get_image_version(
     image_version_uuid: Uuid,
     state: &AppState,
-) -> Result<Vec<u8>> {
+) -> Result<Option<ImageVersion>> {
     let image_version = sqlx::query_as!(
         ImageVersion,
-        "SELECT id, original_image_id, project_id, tool_id, text_result, created_at FROM image_versions WHERE project_id = $1 AND id = $2",
-        project_id,
+        "SELECT id, original_image_id, project_id, tool_id, text_result, skipped, created_at, cache_key FROM image_versions WHERE id = $1",
         image_version_uuid
     )
-        .fetch_one(&state.db_pool)
+        .fetch_optional(&state.db_pool)
         .await?;
 
-    let image_path = image_version.get_uri(state);
-    let image_data = tokio::fs::read(image_path).await?;
-
-    Ok(image_data)
+    Ok(image_version)
 }
 
-pub async fn load_image_versions_zip(
+pub async fn load_image_version(
     project_id: Uuid,
-    tool_id: Uuid,
+    image_version_uuid: Uuid,
     state: &AppState,
 ) -> Result<Vec<u8>> {
-    let image_versions = sqlx::query_as!(
+    let image_version = sqlx::query_as!(
         ImageVersion,
-        "SELECT id, original_image_id, project_id, tool_id, text_result, created_at FROM image_versions WHERE project_id = $1 AND tool_id = $2",
+        "SELECT id, original_image_id, project_id, tool_id, text_result, skipped, created_at, cache_key FROM image_versions WHERE project_id = $1 AND id = $2",
         project_id,
-        tool_id
+        image_version_uuid
     )
-        .fetch_all(&state.db_pool)
+        .fetch_one(&state.db_pool)
         .await?;
 
-    let mut buffer = Vec::new();
-    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(&mut buffer));
+    let image_path = image_version.get_uri();
+    let image_data = state.storage.read(&image_path).await?;
 
-    let options = zip::write::SimpleFileOptions::default()
-        .compression_method(zip::CompressionMethod::Deflated);
+    Ok(image_data)
+}
 
-    for image_version in image_versions {
-        let image_path = image_version.get_uri(state);
-        if let Ok(image_data) = tokio::fs::read(image_path).await {
-            zip.start_file(format!("{}.png", image_version.id), options)?;
-            zip.write_all(&image_data)?;
+/// Streams a zip archive of the selected image versions of a project.
+pub async fn load_image_versions_zip(
+    project_id: Uuid,
+    selection: ExportSelection,
+    state: &AppState,
+) -> Result<Body> {
+    let tools = get_applied_tools(project_id, state).await?;
+    if let ExportSelection::Tool(tool_id) = selection {
+        if !tools.iter().any(|tool| tool.id == tool_id) {
+            return Err(AppError::EntityNotFound);
         }
     }
 
-    zip.finish()?;
+    let image_versions = get_image_versions(project_id, state).await?;
+    let originals = image::controller::get_original_images(project_id, state).await?;
 
-    Ok(buffer)
+    Ok(export::image_versions_zip(
+        selection,
+        image_versions,
+        &originals,
+        &tools,
+        state,
+    ))
 }
@@ -0,0 +1,387 @@
+use crate::error::Result;
+use crate::image::model::Image;
+use crate::tool::model::{ImageVersion, Tool};
+use crate::AppState;
+use axum::body::{Body, Bytes};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tokio::runtime::Handle;
+use tokio::sync::mpsc;
+use tracing::warn;
+use uuid::Uuid;
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+/// The size of the chunks of the archive sent in the body of the response.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The image versions of a project to put in an archive of results.
+#[derive(Debug, Clone, Copy)]
+pub enum ExportSelection {
+    /// The versions of the last tools of the pipeline, whose output no other tool takes.
+    Final,
+    /// The versions of every tool of the pipeline.
+    All,
+    /// The versions of a single tool.
+    Tool(Uuid),
+}
+
+impl ExportSelection {
+    /// The tools whose image versions are exported.
+    fn tools(self, tools: &[Tool]) -> HashSet<Uuid> {
+        match self {
+            ExportSelection::All => tools.iter().map(|tool| tool.id).collect(),
+            ExportSelection::Tool(tool_id) => HashSet::from([tool_id]),
+            ExportSelection::Final => {
+                let parents: HashMap<Uuid, Option<Uuid>> =
+                    tools.iter().map(|tool| (tool.id, tool.parent_id)).collect();
+                let disabled: HashSet<Uuid> = tools
+                    .iter()
+                    .filter(|tool| !tool.enabled)
+                    .map(|tool| tool.id)
+                    .collect();
+
+                // the input of a tool is the output of its closest enabled ancestor, since the
+                // disabled tools are bypassed
+                let mut inputs = HashSet::new();
+                for tool in tools.iter().filter(|tool| tool.enabled) {
+                    let mut parent = tool.parent_id;
+                    while let Some(parent_id) = parent.filter(|id| disabled.contains(id)) {
+                        parent = parents.get(&parent_id).copied().flatten();
+                    }
+                    inputs.extend(parent);
+                }
+
+                tools
+                    .iter()
+                    .filter(|tool| tool.enabled && !inputs.contains(&tool.id))
+                    .map(|tool| tool.id)
+                    .collect()
+            }
+        }
+    }
+}
+
+/// An image version in an archive of results, described in its `manifest.json`.
+#[derive(Debug, Serialize)]
+pub struct ManifestEntry {
+    /// The path of the image in the archive, none if the tool only had a text result.
+    pub file: Option<String>,
+    /// The path of the text result in the archive, if any.
+    pub text_file: Option<String>,
+    pub image_version_id: Uuid,
+    pub original_image_id: Uuid,
+    pub original_name: String,
+    pub tool_id: Uuid,
+    /// The position of the tool in the pipeline, 1 being the first tool.
+    pub step: i32,
+    pub procedure: String,
+    pub skipped: bool,
+    pub text_result: Option<String>,
+    pub created_at: DateTime<Utc>,
+    /// Why the image couldn't be put in the archive, e.g. its file was deleted meanwhile.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+struct ExportEntry {
+    manifest: ManifestEntry,
+    image_path: PathBuf,
+    image_file: String,
+}
+
+/// Streams a zip archive of the selected image versions, in a folder per original image, each
+/// named after the step that produced it, with the text results as `.txt` files next to them
+/// and a `manifest.json` describing them.
+pub fn image_versions_zip(
+    selection: ExportSelection,
+    mut image_versions: Vec<ImageVersion>,
+    originals: &[Image],
+    tools: &[Tool],
+    state: &AppState,
+) -> Body {
+    let selected_tools = selection.tools(tools);
+    let tools: HashMap<Uuid, &Tool> = tools.iter().map(|tool| (tool.id, tool)).collect();
+
+    // a folder per original, named after it
+    let mut folder_names = HashSet::new();
+    let folders: HashMap<Uuid, (&Image, String)> = originals
+        .iter()
+        .map(|image| {
+            let folder = unique_name(folder_name(&image.name), "", &mut folder_names);
+            (image.id, (image, folder))
+        })
+        .collect();
+
+    image_versions.retain(|image_version| selected_tools.contains(&image_version.tool_id));
+    image_versions.sort_by_key(|image_version| {
+        let step = tools.get(&image_version.tool_id).map(|tool| tool.position);
+        (image_version.original_image_id, step)
+    });
+
+    let mut names = HashSet::new();
+    let entries = image_versions
+        .into_iter()
+        .filter_map(|image_version| {
+            let (original, folder) = folders.get(&image_version.original_image_id)?;
+            let tool = tools.get(&image_version.tool_id)?;
+
+            let base = format!("{folder}/{:02}_{}", tool.position, tool.procedure);
+            let image_file = unique_name(base.clone(), ".png", &mut names);
+            let text_file = image_version
+                .text_result
+                .is_some()
+                .then(|| unique_name(base, ".txt", &mut names));
+
+            Some(ExportEntry {
+                image_path: image_version.get_uri(),
+                image_file,
+                manifest: ManifestEntry {
+                    file: None,
+                    text_file,
+                    image_version_id: image_version.id,
+                    original_image_id: original.id,
+                    original_name: original.name.clone(),
+                    tool_id: tool.id,
+                    step: tool.position,
+                    procedure: tool.procedure.clone(),
+                    skipped: image_version.skipped,
+                    text_result: image_version.text_result,
+                    created_at: image_version.created_at,
+                    error: None,
+                },
+            })
+        })
+        .collect();
+
+    stream_zip(entries, state)
+}
+
+/// Writes the archive in a blocking task, each entry sent to the client as soon as it is
+/// compressed, so neither the images nor the archive are ever whole in memory.
+fn stream_zip(entries: Vec<ExportEntry>, state: &AppState) -> Body {
+    let (sender, receiver) = mpsc::channel::<std::io::Result<Bytes>>(4);
+    let state = state.clone();
+    let handle = Handle::current();
+
+    tokio::task::spawn_blocking(move || {
+        let writer = BodyWriter {
+            sender: sender.clone(),
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        };
+
+        if let Err(err) = write_zip(writer, entries, &handle, &state) {
+            // the client gets a truncated archive instead of one that seems complete
+            warn!(?err, "Failed to write the zip archive");
+            let _ = sender.blocking_send(Err(std::io::Error::other(err.to_string())));
+        }
+    });
+
+    Body::from_stream(futures::stream::unfold(
+        receiver,
+        |mut receiver| async move { receiver.recv().await.map(|chunk| (chunk, receiver)) },
+    ))
+}
+
+fn write_zip(
+    writer: BodyWriter,
+    entries: Vec<ExportEntry>,
+    handle: &Handle,
+    state: &AppState,
+) -> Result<()> {
+    let mut zip = ZipWriter::new_stream(writer);
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+
+    let mut manifest = Vec::with_capacity(entries.len());
+    for mut entry in entries {
+        // tools with a text result, e.g. OCR, don't write an image
+        if entry.manifest.text_result.is_none() {
+            match handle.block_on(state.storage.read(&entry.image_path)) {
+                Ok(image_bytes) => {
+                    zip.start_file(entry.image_file.as_str(), options)?;
+                    zip.write_all(&image_bytes)?;
+                    entry.manifest.file = Some(entry.image_file);
+                }
+                Err(err) => {
+                    warn!(
+                        ?err,
+                        image_version = ?entry.manifest.image_version_id,
+                        "Failed to read an image version to export"
+                    );
+                    entry.manifest.error = Some(err.to_string());
+                }
+            }
+        }
+
+        if let (Some(text_file), Some(text_result)) =
+            (&entry.manifest.text_file, &entry.manifest.text_result)
+        {
+            zip.start_file(text_file.as_str(), options)?;
+            zip.write_all(text_result.as_bytes())?;
+        }
+
+        manifest.push(entry.manifest);
+    }
+
+    zip.start_file("manifest.json", options)?;
+    serde_json::to_writer_pretty(&mut zip, &manifest)?;
+
+    zip.finish()?.into_inner().flush()?;
+    Ok(())
+}
+
+/// Sends what the archive writer writes as the chunks of the body of the response.
+struct BodyWriter {
+    sender: mpsc::Sender<std::io::Result<Bytes>>,
+    buffer: Vec<u8>,
+}
+
+impl Write for BodyWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let chunk = std::mem::replace(&mut self.buffer, Vec::with_capacity(CHUNK_SIZE));
+        self.sender
+            .blocking_send(Ok(chunk.into()))
+            // the client went away
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))
+    }
+}
+
+/// Names the folder of an original after the stem of its name, e.g. `photo` for `photo.jpg`,
+/// without what would take it out of the archive or into another folder, e.g. `..` or `a\b`.
+fn folder_name(name: &str) -> String {
+    let stem = Path::new(name)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| name.to_string());
+
+    let stem: String = stem
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    match stem.trim().trim_matches('.') {
+        "" => "image".to_string(),
+        stem => stem.to_string(),
+    }
+}
+
+/// Names a file after `base`, suffixed with a counter if an earlier file took the name already,
+/// e.g. two originals named `photo.jpg`, regardless of case since some filesystems ignore it.
+fn unique_name(base: String, extension: &str, names: &mut HashSet<String>) -> String {
+    let mut name = format!("{base}{extension}");
+    let mut counter = 1;
+    while !names.insert(name.to_lowercase()) {
+        counter += 1;
+        name = format!("{base}_{counter}{extension}");
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tool::export::{folder_name, unique_name, ExportSelection};
+    use crate::tool::model::Tool;
+    use serde_json::json;
+    use std::collections::HashSet;
+    use uuid::Uuid;
+
+    fn tool(position: i32, parent_id: Option<Uuid>, enabled: bool) -> Tool {
+        Tool {
+            id: Uuid::new_v4(),
+            project_id: Uuid::nil(),
+            position,
+            parent_id,
+            procedure: "binarize".to_string(),
+            parameters: json!({}),
+            condition: None,
+            enabled,
+            hash: None,
+        }
+    }
+
+    #[test]
+    fn test_select_final_tools() {
+        // root -> child -> disabled leaf, and root -> branch
+        let root = tool(0, None, true);
+        let child = tool(1, Some(root.id), true);
+        let disabled_leaf = tool(2, Some(child.id), false);
+        let branch = tool(3, Some(root.id), true);
+        let tools = vec![root, child, disabled_leaf, branch];
+
+        let selected = ExportSelection::Final.tools(&tools);
+        assert_eq!(selected, HashSet::from([tools[1].id, tools[3].id]));
+    }
+
+    #[test]
+    fn test_select_final_tools_through_disabled_tools() {
+        // the child of a disabled tool takes the output of the root, so the root isn't final
+        let root = tool(0, None, true);
+        let disabled = tool(1, Some(root.id), false);
+        let leaf = tool(2, Some(disabled.id), true);
+        let tools = vec![root, disabled, leaf];
+
+        let selected = ExportSelection::Final.tools(&tools);
+        assert_eq!(selected, HashSet::from([tools[2].id]));
+    }
+
+    #[test]
+    fn test_select_all_and_single_tools() {
+        let root = tool(0, None, true);
+        let disabled = tool(1, Some(root.id), false);
+        let tools = vec![root, disabled];
+
+        assert_eq!(
+            ExportSelection::All.tools(&tools),
+            HashSet::from([tools[0].id, tools[1].id])
+        );
+        assert_eq!(
+            ExportSelection::Tool(tools[1].id).tools(&tools),
+            HashSet::from([tools[1].id])
+        );
+    }
+
+    #[test]
+    fn test_folder_name() {
+        assert_eq!(folder_name("photo.jpg"), "photo");
+        assert_eq!(folder_name("holidays/photo.jpg"), "photo");
+        assert_eq!(folder_name("a\\b.png"), "a_b");
+        assert_eq!(folder_name(".."), "image");
+        assert_eq!(folder_name(".png"), "png");
+        assert_eq!(folder_name(""), "image");
+    }
+
+    #[test]
+    fn test_unique_name() {
+        let mut names = HashSet::new();
+
+        assert_eq!(unique_name("photo".into(), ".png", &mut names), "photo.png");
+        assert_eq!(
+            unique_name("photo".into(), ".png", &mut names),
+            "photo_2.png"
+        );
+        assert_eq!(
+            unique_name("Photo".into(), ".png", &mut names),
+            "Photo_3.png"
+        );
+        assert_eq!(unique_name("photo".into(), ".txt", &mut names), "photo.txt");
+    }
+}
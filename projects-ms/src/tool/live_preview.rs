@@ -0,0 +1,409 @@
+use crate::error::AppError::Forbidden;
+use crate::error::{AppError, Result};
+use crate::project::controller::can_modify;
+use crate::tool::amqp::message::OutputType::Text;
+use crate::tool::amqp::message::{ErrorObject, ResponseMessage, ResponseStatus};
+use crate::tool::model::RequestedTool;
+use crate::tool::{queue, validation, websocket};
+use crate::user::AccessTokenClaims;
+use crate::{config, image, AppState};
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderValue};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::{debug_handler, Router};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::time::Instant;
+use tracing::{debug, error, info};
+use uuid::Uuid;
+
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/projects/{project_id}/tools/live/{preview_id}",
+            get(download_live_preview),
+        )
+        .with_state(state)
+}
+
+/// A request of a client to preview a single tool on the proxy of an image.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LivePreviewRequest {
+    pub image_id: Uuid,
+    #[serde(flatten)]
+    pub tool: RequestedTool,
+}
+
+/// The result of a live preview, sent to the client that requested it.
+#[derive(Debug, Serialize)]
+pub struct LivePreviewResult {
+    pub id: Uuid,
+    pub image_id: Uuid,
+    pub procedure: String,
+    pub text_result: Option<String>,
+    pub url: String,
+}
+
+#[derive(Default)]
+struct LivePreviewClient {
+    /// The request waiting for a tool and the instant it is given up on.
+    in_flight: Option<(Uuid, Instant)>,
+    /// The latest request received while another one was in flight.
+    pending: Option<LivePreviewRequest>,
+    /// The priority the requests of the client are published with.
+    priority: u8,
+    /// The last result sent to the client, deleted once replaced.
+    last_result: Option<Uuid>,
+}
+
+impl LivePreviewClient {
+    /// Takes the pending request to be published, if no other request is in flight.
+    fn next_request(&mut self, state: &AppState) -> Option<(Uuid, LivePreviewRequest)> {
+        if self.in_flight.is_some() {
+            return None;
+        }
+
+        let request = self.pending.take()?;
+        let message_uuid = Uuid::new_v4();
+        let timeout = state.config.tool_timeout(&request.tool.procedure);
+        self.in_flight = Some((message_uuid, Instant::now() + timeout));
+        Some((message_uuid, request))
+    }
+}
+
+struct InFlightPreview {
+    project_id: Uuid,
+    user_id: Uuid,
+    image_id: Uuid,
+    procedure: String,
+    /// The instant the request is given up on, its result dropped if it arrives later.
+    deadline: Instant,
+}
+
+/// The live previews of the connected clients.
+/// Each client has at most one request waiting for a tool, and only the latest of the requests
+/// that arrive meanwhile is kept, e.g. while a slider is being dragged.
+#[derive(Default)]
+pub struct LivePreviews {
+    clients: DashMap<(Uuid, Uuid), LivePreviewClient>, // project_uuid, user_uuid -> LivePreviewClient
+    in_flight: DashMap<Uuid, InFlightPreview>,         // message_uuid -> InFlightPreview
+}
+
+impl LivePreviews {
+    /// Whether the message is the result of a live preview.
+    pub fn is_live_preview(&self, message_id: &Uuid) -> bool {
+        self.in_flight.contains_key(message_id)
+    }
+}
+
+/// Forgets the requests whose tool didn't answer in time, e.g. because the message expired in
+/// the queue, deleting their output and publishing the requests their clients kept meanwhile.
+pub async fn remove_expired(state: &AppState) {
+    let now = Instant::now();
+    let expired: Vec<Uuid> = state
+        .live_previews
+        .in_flight
+        .iter()
+        .filter(|preview| preview.deadline <= now)
+        .map(|preview| *preview.key())
+        .collect();
+
+    for message_uuid in expired {
+        let Some((_, preview)) = state.live_previews.in_flight.remove(&message_uuid) else {
+            // the result arrived meanwhile
+            continue;
+        };
+
+        let key = (preview.project_id, preview.user_id);
+        let output_path = config::generate_live_preview_output_uri(key.0, key.1, message_uuid);
+        let _ = state.storage.delete(&output_path).await;
+
+        let next_request = {
+            let Some(mut client) = state.live_previews.clients.get_mut(&key) else {
+                continue;
+            };
+
+            if client.in_flight.is_some_and(|(id, _)| id == message_uuid) {
+                client.in_flight = None;
+            }
+            client.next_request(state)
+        };
+
+        debug!(?message_uuid, "Removed an expired live preview request");
+
+        if let Some((next_uuid, request)) = next_request {
+            if let Err(err) = publish_live_preview(next_uuid, key.0, key.1, request, state).await {
+                send_live_preview_error(key.0, key.1, err, state).await;
+            }
+        }
+    }
+}
+
+/// Handles a live preview request sent over the websocket of a project.
+pub async fn request_live_preview(
+    project_uuid: Uuid,
+    user: &AccessTokenClaims,
+    request: LivePreviewRequest,
+    state: &AppState,
+) -> Result<()> {
+    validation::validate_tool(&request.tool, state)?;
+
+    let key = (project_uuid, user.sub);
+    let message_uuid = {
+        let mut client = state.live_previews.clients.entry(key).or_default();
+        client.priority = user.role.priority(&state.config);
+
+        if client
+            .in_flight
+            .is_some_and(|(_, deadline)| deadline > Instant::now())
+        {
+            debug!("Keeping live preview request until the previous one finishes");
+            client.pending = Some(request);
+            return Ok(());
+        }
+
+        // the request in flight expired, its result is dropped if it ever arrives, and the
+        // request kept meanwhile is older than this one
+        if let Some((expired_uuid, _)) = client.in_flight.take() {
+            state.live_previews.in_flight.remove(&expired_uuid);
+        }
+        client.pending = None;
+
+        let message_uuid = Uuid::new_v4();
+        let timeout = state.config.tool_timeout(&request.tool.procedure);
+        client.in_flight = Some((message_uuid, Instant::now() + timeout));
+        message_uuid
+    };
+
+    publish_live_preview(message_uuid, project_uuid, user.sub, request, state).await
+}
+
+async fn publish_live_preview(
+    message_uuid: Uuid,
+    project_uuid: Uuid,
+    user_uuid: Uuid,
+    request: LivePreviewRequest,
+    state: &AppState,
+) -> Result<()> {
+    let result = send_live_preview(message_uuid, project_uuid, user_uuid, &request, state).await;
+
+    if result.is_err() {
+        state.live_previews.in_flight.remove(&message_uuid);
+        if let Some(mut client) = state
+            .live_previews
+            .clients
+            .get_mut(&(project_uuid, user_uuid))
+        {
+            client.in_flight = None;
+        }
+    }
+
+    result
+}
+
+async fn send_live_preview(
+    message_uuid: Uuid,
+    project_uuid: Uuid,
+    user_uuid: Uuid,
+    request: &LivePreviewRequest,
+    state: &AppState,
+) -> Result<()> {
+    let image = image::controller::get_original_image(project_uuid, request.image_id, state)
+        .await?
+        .ok_or(AppError::EntityNotFound)?;
+
+    let image_input_path = image::controller::get_proxy_image_uri(&image, state).await?;
+    let image_output_path =
+        config::generate_live_preview_output_uri(project_uuid, user_uuid, message_uuid);
+
+    let timeout = state.config.tool_timeout(&request.tool.procedure);
+    state.live_previews.in_flight.insert(
+        message_uuid,
+        InFlightPreview {
+            project_id: project_uuid,
+            user_id: user_uuid,
+            image_id: image.id,
+            procedure: request.tool.procedure.clone(),
+            deadline: Instant::now() + timeout,
+        },
+    );
+
+    let priority = state
+        .live_previews
+        .clients
+        .get(&(project_uuid, user_uuid))
+        .map(|client| client.priority)
+        .unwrap_or_default();
+
+    queue::send_request_to_rabbitmq(
+        message_uuid,
+        &image_input_path,
+        &image_output_path,
+        &request.tool,
+        timeout,
+        priority,
+        state,
+    )
+    .await?;
+
+    debug!(?message_uuid, image = ?image.id, "Published live preview");
+
+    Ok(())
+}
+
+/// Sends the result of a live preview to its client and publishes the request that was kept
+/// while it was in flight, if any.
+pub async fn handle_result_message(message: ResponseMessage, state: &AppState) -> Result<()> {
+    let Some((message_uuid, preview)) = state
+        .live_previews
+        .in_flight
+        .remove(&message.correlation_id)
+    else {
+        return Ok(());
+    };
+
+    let key = (preview.project_id, preview.user_id);
+    let output_path = config::generate_live_preview_output_uri(key.0, key.1, message_uuid);
+
+    let (late, previous_result, next_request) = {
+        let Some(mut client) = state.live_previews.clients.get_mut(&key) else {
+            // the client disconnected meanwhile
+            let _ = state.storage.delete(&output_path).await;
+            return Ok(());
+        };
+
+        // a result arriving after its deadline may be older than the one the client has
+        let is_current = client.in_flight.is_some_and(|(id, _)| id == message_uuid);
+        let late = !is_current || preview.deadline <= Instant::now();
+        if is_current {
+            client.in_flight = None;
+        }
+
+        let previous_result = match message.status {
+            ResponseStatus::Success { .. } if !late => client.last_result.replace(message_uuid),
+            _ => None,
+        };
+
+        let next_request = client.next_request(state);
+
+        (late, previous_result, next_request)
+    };
+
+    if late {
+        debug!(?message_uuid, "Dropping a late live preview result");
+        let _ = state.storage.delete(&output_path).await;
+    }
+
+    if let Some(previous_result) = previous_result {
+        let previous_path = config::generate_live_preview_output_uri(key.0, key.1, previous_result);
+        let _ = state.storage.delete(&previous_path).await;
+    }
+
+    let notification = match message.status {
+        _ if late => None,
+        ResponseStatus::Success { output } => {
+            let result = LivePreviewResult {
+                id: message_uuid,
+                image_id: preview.image_id,
+                procedure: preview.procedure,
+                text_result: (output.kind == Text).then_some(output.text).flatten(),
+                url: format!(
+                    "{}/api/v1/projects/{}/tools/live/{}",
+                    state.config.picturas_public_url, key.0, message_uuid
+                ),
+            };
+            Some(json!({ "live_preview": result }))
+        }
+        ResponseStatus::Error { error } => Some(json!({ "live_preview_error": error })),
+    };
+
+    if let Some(notification) = notification {
+        if let Err(err) = websocket::send_ws_message(state, key.0, key.1, notification).await {
+            error!("Failed to send message to websocket: {}", err);
+        }
+    }
+
+    if let Some((next_uuid, request)) = next_request {
+        if let Err(err) = publish_live_preview(next_uuid, key.0, key.1, request, state).await {
+            send_live_preview_error(key.0, key.1, err, state).await;
+        }
+    }
+
+    Ok(())
+}
+
+/// Notifies the client that a live preview request couldn't be handled, along with the
+/// invalid fields of the request if it was rejected, as in the body of a 422 response.
+pub async fn send_live_preview_error(
+    project_uuid: Uuid,
+    user_uuid: Uuid,
+    err: AppError,
+    state: &AppState,
+) {
+    info!(?err, "Failed to handle live preview request");
+
+    let code = match err {
+        AppError::Validation(_) => "VALIDATION_ERROR",
+        _ => "LIVE_PREVIEW_ERROR",
+    };
+    let mut error = json!(ErrorObject {
+        code: code.to_string(),
+        message: err.to_string(),
+    });
+    if let AppError::Validation(errors) = &err {
+        error["details"] = json!(errors);
+    }
+
+    if let Err(err) = websocket::send_ws_message(
+        state,
+        project_uuid,
+        user_uuid,
+        json!({ "live_preview_error": error }),
+    )
+    .await
+    {
+        error!("Failed to send message to websocket: {}", err);
+    }
+}
+
+/// Forgets the live previews of a client that disconnected, along with their results.
+pub async fn remove_client(project_uuid: Uuid, user_uuid: Uuid, state: &AppState) {
+    // results still in flight are discarded once they arrive
+    state
+        .live_previews
+        .clients
+        .remove(&(project_uuid, user_uuid));
+
+    let _ = state
+        .storage
+        .delete_folder(&config::generate_live_preview_folder_uri(
+            project_uuid,
+            user_uuid,
+        ))
+        .await;
+}
+
+#[debug_handler]
+async fn download_live_preview(
+    Path((project_id, preview_id)): Path<(Uuid, Uuid)>,
+    user: AccessTokenClaims,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse> {
+    if !can_modify(project_id, user.sub, &state).await? {
+        return Err(Forbidden);
+    }
+
+    let path = config::generate_live_preview_output_uri(project_id, user.sub, preview_id);
+    let image_bytes = state
+        .storage
+        .read(&path)
+        .await
+        .map_err(|_| AppError::EntityNotFound)?;
+
+    Ok((
+        [(header::CONTENT_TYPE, HeaderValue::from_static("image/png"))],
+        image_bytes,
+    ))
+}
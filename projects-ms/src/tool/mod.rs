@@ -1,6 +1,12 @@
 pub mod amqp;
+pub mod cache;
+pub mod catalogue;
+pub mod condition;
 pub mod controller;
-mod model;
+pub mod export;
+pub mod live_preview;
+pub mod model;
 pub mod queue;
 pub mod router;
-mod websocket;
+pub mod validation;
+pub mod websocket;
@@ -1,13 +1,15 @@
-use crate::{config, AppState};
+use crate::config;
+use crate::tool::condition::ToolCondition;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use sqlx::types::JsonValue;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 use uuid::Uuid;
 
 /// The tool model.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Tool {
     /// The unique identifier of the tool.
     pub id: Uuid,
@@ -15,10 +17,140 @@ pub struct Tool {
     pub project_id: Uuid,
     /// The index of the tool in the project (0 should be the first tool to be applied).
     pub position: i32,
+    /// The tool whose output is the input of this tool, none if it takes the original image.
+    pub parent_id: Option<Uuid>,
     /// The procedure to be applied to the image.
     pub procedure: String,
     /// The parameters of the procedure.
     pub parameters: JsonValue,
+    /// The condition the input image must match for the tool to run, if any.
+    pub condition: Option<JsonValue>,
+    /// Whether the tool is applied, a disabled tool passing its input as is to its children.
+    pub enabled: bool,
+    /// The hash of the procedure, parameters and condition of this tool and of its ancestors.
+    pub hash: Option<String>,
+}
+
+impl Tool {
+    /// Hashes a tool, chaining it with the hash of its parent, so two tools have the same
+    /// hash only if they and every one of their ancestors are the same.
+    pub fn hash(
+        procedure: &str,
+        parameters: &JsonValue,
+        condition: Option<&JsonValue>,
+        enabled: bool,
+        upstream_hash: Option<&str>,
+    ) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(upstream_hash.unwrap_or_default());
+        hasher.update([0]);
+        hasher.update(procedure);
+        hasher.update([0]);
+        // the keys of serde_json maps are sorted, so equal parameters serialize the same way
+        hasher.update(parameters.to_string());
+        // tools without a condition keep the hashes they had before conditions existed
+        if let Some(condition) = condition {
+            hasher.update([0]);
+            hasher.update(condition.to_string());
+        }
+        // the tools after a disabled tool get another input, so they can't keep their hashes
+        if !enabled {
+            hasher.update([0]);
+            hasher.update("disabled");
+        }
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+/// The tools of a project as a tree, where each tool takes the output of its parent as input,
+/// so the output of a tool can feed several branches.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ToolGraph {
+    /// The tools of the project, every tool after its parent.
+    pub nodes: Vec<Tool>,
+}
+
+/// An edge of the tool graph, the output of a tool being the input of another.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ToolEdge {
+    pub from: Uuid,
+    pub to: Uuid,
+}
+
+impl ToolGraph {
+    /// Sorts the tools so every tool comes after its parent, siblings in the order of their
+    /// positions.
+    /// Returns none if a tool has a parent outside the tools or if the edges form a cycle.
+    pub fn new(mut tools: Vec<Tool>) -> Option<Self> {
+        tools.sort_by_key(|tool| tool.position);
+
+        let total = tools.len();
+        let mut children: HashMap<Option<Uuid>, Vec<Tool>> = HashMap::new();
+        for tool in tools {
+            children.entry(tool.parent_id).or_default().push(tool);
+        }
+
+        let mut nodes = Vec::with_capacity(total);
+        let mut stack = children.remove(&None).unwrap_or_default();
+        stack.reverse();
+
+        while let Some(tool) = stack.pop() {
+            if let Some(tool_children) = children.remove(&Some(tool.id)) {
+                stack.extend(tool_children.into_iter().rev());
+            }
+            nodes.push(tool);
+        }
+
+        // the tools that weren't reached have no root ancestor
+        (nodes.len() == total).then_some(Self { nodes })
+    }
+
+    pub fn contains(&self, tool_id: Uuid) -> bool {
+        self.nodes.iter().any(|tool| tool.id == tool_id)
+    }
+
+    pub fn edges(&self) -> Vec<ToolEdge> {
+        self.nodes
+            .iter()
+            .filter_map(|tool| {
+                Some(ToolEdge {
+                    from: tool.parent_id?,
+                    to: tool.id,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the ids of the tool and of every tool that depends on its output.
+    pub fn descendants(&self, tool_id: Uuid) -> HashSet<Uuid> {
+        let mut descendants = HashSet::from([tool_id]);
+        for tool in &self.nodes {
+            if tool
+                .parent_id
+                .is_some_and(|parent_id| descendants.contains(&parent_id))
+            {
+                descendants.insert(tool.id);
+            }
+        }
+        descendants
+    }
+
+    /// Recomputes the hashes of the tools from their parents.
+    pub fn rehash(&mut self) {
+        let mut hashes: HashMap<Uuid, String> = HashMap::new();
+        for tool in &mut self.nodes {
+            let upstream_hash = tool.parent_id.and_then(|parent_id| hashes.get(&parent_id));
+            let hash = Tool::hash(
+                &tool.procedure,
+                &tool.parameters,
+                tool.condition.as_ref(),
+                tool.enabled,
+                upstream_hash.map(|h| h.as_str()),
+            );
+            hashes.insert(tool.id, hash.clone());
+            tool.hash = Some(hash);
+        }
+    }
 }
 
 /// An image with a tool applied to it.
@@ -34,25 +166,83 @@ pub struct ImageVersion {
     pub tool_id: Uuid,
     /// The text result of the tool if any (e.g. OCR result).
     pub text_result: Option<String>,
+    /// Whether the condition of the tool didn't match, the image version being its input as is.
+    pub skipped: bool,
     /// The timestamp of the image version creation.
     pub created_at: DateTime<Utc>,
+    /// The key of the cached result the image version uses, if any.
+    #[serde(skip)]
+    pub cache_key: Option<String>,
 }
 
 impl ImageVersion {
-    pub fn get_uri(&self, state: &AppState) -> PathBuf {
-        config::generate_image_version_output_uri(
-            self.project_id,
-            self.original_image_id,
-            self.id,
-            state,
-        )
+    pub fn get_uri(&self) -> PathBuf {
+        match &self.cache_key {
+            Some(cache_key) => config::generate_cached_result_uri(cache_key),
+            None => config::generate_image_version_output_uri(
+                self.project_id,
+                self.original_image_id,
+                self.id,
+            ),
+        }
     }
 }
 
+/// An image with a tool applied to it by a preview job, rendered from the proxy of the image.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PreviewVersion {
+    /// The unique identifier of the preview version, the same as the job step that created it.
+    pub id: Uuid,
+    /// The image associated with the preview version.
+    pub original_image_id: Uuid,
+    /// The project associated with the preview version.
+    pub project_id: Uuid,
+    /// The tool that created this preview version.
+    pub tool_id: Uuid,
+    /// The text result of the tool if any (e.g. OCR result).
+    pub text_result: Option<String>,
+    /// Whether the condition of the tool didn't match, the preview version being its input as is.
+    pub skipped: bool,
+    /// The timestamp of the preview version creation.
+    pub created_at: DateTime<Utc>,
+}
+
+impl PreviewVersion {
+    pub fn get_uri(&self) -> PathBuf {
+        config::generate_preview_output_uri(self.project_id, self.original_image_id, self.id)
+    }
+}
+
+/// A result of a tool shared by the image versions of every project that applied the same
+/// steps to the same image bytes.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CachedResult {
+    /// The hash of the input image and of every step applied to it.
+    pub key: String,
+    /// The text result of the tool if any (e.g. OCR result).
+    pub text_result: Option<String>,
+    /// The amount of image versions using the result.
+    pub ref_count: i32,
+    /// The timestamp of the result creation.
+    pub created_at: DateTime<Utc>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct RequestedTool {
     pub procedure: String,
     pub parameters: HashMap<String, serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub condition: Option<ToolCondition>,
+    #[serde(default = "enabled_by_default", skip_serializing_if = "is_enabled")]
+    pub enabled: bool,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+fn is_enabled(enabled: &bool) -> bool {
+    *enabled
 }
 
 impl TryInto<RequestedTool> for Tool {
@@ -62,6 +252,221 @@ impl TryInto<RequestedTool> for Tool {
         Ok(RequestedTool {
             procedure: self.procedure,
             parameters: serde_json::from_value(self.parameters)?,
+            condition: self.condition.map(serde_json::from_value).transpose()?,
+            enabled: self.enabled,
+        })
+    }
+}
+
+/// The tools of a project as a portable document, e.g. to keep a pipeline in git or to move it
+/// between environments.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PipelineDocument {
+    /// The version of the format of the document.
+    pub schema_version: u32,
+    /// The sha256 of the tools, to detect documents that were corrupted or edited by mistake.
+    pub checksum: String,
+    /// The tools, every tool after the tool it takes the output of.
+    pub tools: Vec<PipelineTool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PipelineTool {
+    #[serde(flatten)]
+    pub tool: RequestedTool,
+    /// The index of the tool this tool takes the output of, none if it takes the original image.
+    pub parent: Option<usize>,
+}
+
+impl PipelineDocument {
+    pub const SCHEMA_VERSION: u32 = 1;
+
+    pub fn new(tools: Vec<PipelineTool>) -> Result<Self, serde_json::Error> {
+        Ok(Self {
+            schema_version: Self::SCHEMA_VERSION,
+            checksum: Self::checksum(&tools)?,
+            tools,
         })
     }
+
+    pub fn checksum(tools: &[PipelineTool]) -> Result<String, serde_json::Error> {
+        // the keys of serde_json maps are sorted, so the same tools always serialize the same way
+        let tools = serde_json::to_value(tools)?;
+        Ok(format!("{:x}", Sha256::digest(tools.to_string())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tool::model::{PipelineDocument, PipelineTool, RequestedTool, Tool, ToolGraph};
+    use serde_json::json;
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    fn tool(position: i32, parent_id: Option<Uuid>) -> Tool {
+        Tool {
+            id: Uuid::new_v4(),
+            project_id: Uuid::nil(),
+            position,
+            parent_id,
+            procedure: "binarize".to_string(),
+            parameters: json!({}),
+            condition: None,
+            enabled: true,
+            hash: None,
+        }
+    }
+
+    #[test]
+    fn test_graph_sorts_tools_after_their_parents() {
+        let root = tool(2, None);
+        let child = tool(0, Some(root.id));
+        let sibling = tool(1, Some(root.id));
+        let other_root = tool(3, None);
+        let ids = [root.id, child.id, sibling.id, other_root.id];
+
+        let graph = ToolGraph::new(vec![other_root, sibling, child, root]).unwrap();
+
+        let order: Vec<Uuid> = graph.nodes.iter().map(|tool| tool.id).collect();
+        assert_eq!(order, ids);
+        assert_eq!(graph.edges().len(), 2);
+        assert_eq!(
+            graph.descendants(ids[0]),
+            ids[..3].iter().copied().collect()
+        );
+    }
+
+    #[test]
+    fn test_graph_rejects_unknown_parents() {
+        let root = tool(0, None);
+        let orphan = tool(1, Some(Uuid::new_v4()));
+
+        assert!(ToolGraph::new(vec![root, orphan]).is_none());
+    }
+
+    #[test]
+    fn test_graph_rejects_cycles() {
+        let root = tool(0, None);
+        let mut first = tool(1, None);
+        let second = tool(2, Some(first.id));
+        first.parent_id = Some(second.id);
+
+        assert!(ToolGraph::new(vec![root, first, second]).is_none());
+    }
+
+    #[test]
+    fn test_hash_chains_ancestors() {
+        let parameters = json!({ "threshold": 128 });
+        let root = Tool::hash("binarize", &parameters, None, true, None);
+        let other_root = Tool::hash("grayscale", &json!({}), None, true, None);
+
+        // the same tool hashes the same way only after the same ancestors
+        let child = Tool::hash("rotate", &json!({}), None, true, Some(&root));
+        assert_eq!(
+            child,
+            Tool::hash("rotate", &json!({}), None, true, Some(&root))
+        );
+        assert_ne!(
+            child,
+            Tool::hash("rotate", &json!({}), None, true, Some(&other_root))
+        );
+        assert_ne!(child, Tool::hash("rotate", &json!({}), None, true, None));
+
+        assert_ne!(
+            root,
+            Tool::hash("binarize", &json!({ "threshold": 64 }), None, true, None)
+        );
+        assert_ne!(
+            root,
+            Tool::hash("binarize", &parameters, Some(&json!({})), true, None)
+        );
+        assert_ne!(root, Tool::hash("binarize", &parameters, None, false, None));
+    }
+
+    #[test]
+    fn test_rehash_follows_parents() {
+        let root = tool(0, None);
+        let mut child = tool(1, Some(root.id));
+        child.procedure = "rotate".to_string();
+        let mut graph = ToolGraph::new(vec![root, child]).unwrap();
+
+        graph.rehash();
+
+        let root_hash = graph.nodes[0].hash.clone().unwrap();
+        assert_eq!(
+            root_hash,
+            Tool::hash("binarize", &json!({}), None, true, None)
+        );
+        assert_eq!(
+            graph.nodes[1].hash,
+            Some(Tool::hash(
+                "rotate",
+                &json!({}),
+                None,
+                true,
+                Some(&root_hash)
+            ))
+        );
+    }
+
+    fn pipeline_tool(
+        parameters: &[(&str, serde_json::Value)],
+        parent: Option<usize>,
+    ) -> PipelineTool {
+        PipelineTool {
+            tool: RequestedTool {
+                procedure: "binarize".to_string(),
+                parameters: parameters
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.clone()))
+                    .collect::<HashMap<_, _>>(),
+                condition: None,
+                enabled: true,
+            },
+            parent,
+        }
+    }
+
+    #[test]
+    fn test_checksum_ignores_the_order_of_parameters() {
+        let tools = vec![
+            pipeline_tool(&[("threshold", json!(128)), ("invert", json!(true))], None),
+            pipeline_tool(&[], Some(0)),
+        ];
+        let same_tools = vec![
+            pipeline_tool(&[("invert", json!(true)), ("threshold", json!(128))], None),
+            pipeline_tool(&[], Some(0)),
+        ];
+
+        assert_eq!(
+            PipelineDocument::checksum(&tools).unwrap(),
+            PipelineDocument::checksum(&same_tools).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_checksum_detects_edited_tools() {
+        let document = PipelineDocument::new(vec![
+            pipeline_tool(&[("threshold", json!(128))], None),
+            pipeline_tool(&[], Some(0)),
+        ])
+        .unwrap();
+
+        let mut edited = document.tools.clone();
+        edited[0]
+            .tool
+            .parameters
+            .insert("threshold".to_string(), json!(64));
+        assert_ne!(
+            PipelineDocument::checksum(&edited).unwrap(),
+            document.checksum
+        );
+
+        let mut reparented = document.tools.clone();
+        reparented[1].parent = None;
+        assert_ne!(
+            PipelineDocument::checksum(&reparented).unwrap(),
+            document.checksum
+        );
+    }
 }
@@ -1,75 +1,39 @@
 use crate::error::AppError;
+use crate::job::model::{Job, JobStep, JobStepStatus};
 use crate::tool::amqp::message::OutputType::Text;
-use crate::tool::amqp::message::ResponseStatus;
+use crate::tool::amqp::message::{ErrorObject, ResponseMessage, ResponseStatus};
 use crate::tool::amqp::rabbit_controller::{RabbitMqConsumer, RabbitMqControllerError};
-use crate::tool::controller::ImageVersionWithUrl;
-use crate::tool::model::{ImageVersion, RequestedTool};
-use crate::tool::{amqp, controller, websocket};
-use crate::{config, AppState};
+use crate::tool::condition::{ImageMetadata, ToolCondition};
+use crate::tool::controller::{ImageVersionWithUrl, PreviewVersionWithUrl};
+use crate::tool::model::{ImageVersion, PreviewVersion, RequestedTool};
+use crate::tool::{amqp, cache, controller, live_preview, websocket};
+use crate::{image, job, AppState};
 use chrono::Utc;
 use serde_json::json;
+use sqlx::PgConnection;
 use std::collections::VecDeque;
-use std::path::{Path, PathBuf};
-use tracing::{error, info};
+use std::path::Path;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
-#[derive(Debug)]
-pub struct QueuedImageApplyTool {
-    pub new_image_uuid: Uuid,
-    pub original_image_uuid: Uuid,
-    pub project_id: Uuid,
-    pub user_id: Uuid,
-    pub image_input_uri: PathBuf,
-    pub image_output_uri: PathBuf,
-    pub missing_tools: VecDeque<(Uuid, RequestedTool)>, // tool_uuid, tool
-}
-
-impl QueuedImageApplyTool {
-    pub fn new_generate_output_uri(
-        project_id: Uuid,
-        user_id: Uuid,
-        original_image_uuid: Uuid,
-        image_input_uri: PathBuf,
-        missing_tools: VecDeque<(Uuid, RequestedTool)>,
-        state: &AppState,
-    ) -> Self {
-        let new_image_uuid = Uuid::new_v4();
-
-        let image_output_uri = config::generate_image_version_output_uri(
-            project_id,
-            original_image_uuid,
-            new_image_uuid,
-            state,
-        );
-
-        Self {
-            new_image_uuid,
-            image_input_uri,
-            image_output_uri,
-            missing_tools,
-            original_image_uuid,
-            project_id,
-            user_id,
-        }
-    }
-}
-
-async fn send_request_to_rabbitmq(
+pub async fn send_request_to_rabbitmq(
+    message_uuid: Uuid,
     image_input_path: &Path,
     image_output_path: &Path,
     tool: &RequestedTool,
+    timeout: Duration,
+    priority: u8,
     state: &AppState,
-) -> Result<Uuid, RabbitMqControllerError> {
-    let message_uuid = Uuid::new_v4();
-
+) -> Result<(), RabbitMqControllerError> {
     let mut parameters = tool.parameters.clone();
     parameters.insert(
         "inputImageURI".to_string(),
-        json!(image_input_path.to_string_lossy().to_string()),
+        json!(state.storage.uri(image_input_path)),
     );
     parameters.insert(
         "outputImageURI".to_string(),
-        json!(image_output_path.to_string_lossy().to_string()),
+        json!(state.storage.uri(image_output_path)),
     );
 
     let message = amqp::message::RequestMessage {
@@ -79,120 +43,579 @@ async fn send_request_to_rabbitmq(
         parameters,
     };
 
-    state.rabbit_mq_controller.publish_request(message).await?;
+    state
+        .rabbit_mq_controller
+        .publish_request(message, timeout, priority)
+        .await?;
 
-    Ok(message_uuid)
+    Ok(())
 }
 
-pub async fn add_to_queue(
-    mut queued_image_apply_tool: QueuedImageApplyTool,
-    state: &AppState,
-) -> Result<(), AppError> {
-    let Some((tool_uuid, requested_tool)) = queued_image_apply_tool.missing_tools.pop_front()
-    else {
-        // no tool to apply
+/// Wakes up the scheduler so it publishes the steps that became ready.
+pub fn schedule(state: &AppState) {
+    state.scheduler_notify.notify_one();
+}
+
+/// Publishes the ready steps of every job, whenever woken up by [`schedule`].
+/// Steps left waiting from a previous run of the service are published on start.
+pub async fn run_scheduler(state: AppState) {
+    // retried steps become ready without anyone waking up the scheduler
+    const SCHEDULE_INTERVAL: Duration = Duration::from_secs(5);
+
+    loop {
+        if let Err(e) = schedule_ready_steps(&state).await {
+            error!("Failed to schedule ready steps: {}", e);
+        }
+
+        let _ = tokio::time::timeout(SCHEDULE_INTERVAL, state.scheduler_notify.notified()).await;
+    }
+}
+
+/// Publishes the ready steps round-robin between users, so a big job of a user doesn't fill the
+/// tool queues ahead of everyone else, while keeping each user under their in-flight limit.
+async fn schedule_ready_steps(state: &AppState) -> Result<(), AppError> {
+    let max_running = state.config.picturas_max_in_flight_steps_per_user;
+    let ready_steps = job::controller::get_ready_steps(max_running, state).await?;
+    if ready_steps.is_empty() {
         return Ok(());
+    }
+
+    let mut running = job::controller::get_running_steps_per_user(state).await?;
+    let mut user_queues: Vec<(Uuid, VecDeque<(Job, JobStep)>)> = vec![];
+
+    for (job, step) in ready_steps {
+        let user_id = job.user_id;
+        match user_queues.iter_mut().find(|(user, _)| *user == user_id) {
+            Some((_, steps)) => steps.push_back((job, step)),
+            None => user_queues.push((user_id, VecDeque::from([(job, step)]))),
+        }
+    }
+
+    // users with less steps waiting for a tool go first
+    user_queues.sort_by_key(|(user, _)| running.get(user).copied().unwrap_or_default());
+
+    loop {
+        let mut published_any = false;
+
+        for (user, steps) in &mut user_queues {
+            let user_running = running.entry(*user).or_default();
+            if *user_running >= max_running {
+                continue;
+            }
+
+            let Some((job, step)) = steps.pop_front() else {
+                continue;
+            };
+
+            let step_id = step.id;
+            match publish_step(step, &job, state).await {
+                Ok(true) => *user_running += 1,
+                Ok(false) => {}
+                Err(e) => error!("Failed to publish job step {}: {}", step_id, e),
+            }
+            published_any = true;
+        }
+
+        if !published_any {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Publishes a step to its tool, using the output of its parent step as input,
+/// or completes it right away if its condition doesn't match or its result is cached.
+/// Returns false if the step isn't waiting for a tool.
+async fn publish_step(step: JobStep, job: &Job, state: &AppState) -> Result<bool, AppError> {
+    let image_input_path = match (step.parent_step_id, step.input_version_id) {
+        (Some(parent_step_id), _) => job::controller::get_step(parent_step_id, state)
+            .await?
+            .ok_or(AppError::EntityNotFound)?
+            .get_output_uri(job),
+        (None, Some(input_version_id)) => controller::get_image_version(input_version_id, state)
+            .await?
+            .ok_or(AppError::EntityNotFound)?
+            .get_uri(),
+        (None, None) => {
+            let image = image::controller::get_original_image(
+                job.project_id,
+                step.original_image_id,
+                state,
+            )
+            .await?
+            .ok_or(AppError::EntityNotFound)?;
+
+            if job.preview {
+                image::controller::get_proxy_image_uri(&image, state).await?
+            } else {
+                image.get_uri()
+            }
+        }
     };
 
-    let image_input_path = &queued_image_apply_tool.image_input_uri;
-    let image_output_path = &queued_image_apply_tool.image_output_uri;
+    if let Some(condition) = step.condition.clone() {
+        let condition: ToolCondition = serde_json::from_value(condition)?;
 
-    if let Some(output_folder) = image_output_path.parent() {
-        tokio::fs::create_dir_all(output_folder).await?;
+        // the tool still runs if the condition can't be evaluated, it may support the image
+        match input_metadata(&step, &image_input_path, job, state).await {
+            Ok(metadata) if !condition.when.matches(&metadata) => {
+                debug!(job = ?job.id, step = ?step.id, ?metadata, "Skipping a step");
+                skip_steps(
+                    step,
+                    condition.skip_following,
+                    &image_input_path,
+                    job,
+                    state,
+                )
+                .await?;
+
+                // the children of the skipped steps are ready now
+                schedule(state);
+                return Ok(false);
+            }
+            Ok(_) => {}
+            Err(e) => warn!(step = ?step.id, "Failed to evaluate the condition of a step: {}", e),
+        }
+    }
+
+    if let Some(cache_key) = step.cache_key.as_deref().filter(|_| !job.preview) {
+        // the reference, the step and its version are saved together, so a step that isn't
+        // queued anymore rolls back the reference it took
+        let mut transaction = state.db_pool.begin().await?;
+        if let Some(cached_result) = cache::acquire(cache_key, &mut transaction).await? {
+            if !job::controller::mark_step_cached(step.id, &mut transaction).await? {
+                return Ok(false);
+            }
+
+            debug!(job = ?job.id, step = ?step.id, cache_key, "Reused a cached result");
+            let step_result =
+                save_step_result(&step, job, cached_result.text_result, &mut transaction).await?;
+            transaction.commit().await?;
+            send_step_result(step_result, job, state).await;
+
+            // the children of the step are ready now
+            schedule(state);
+            return Ok(false);
+        }
     }
 
-    let message_id =
-        send_request_to_rabbitmq(image_input_path, image_output_path, &requested_tool, state)
+    let image_output_path = step.get_output_uri(job);
+
+    let step_id = step.id;
+    let original_image_id = step.original_image_id;
+    let requested_tool: RequestedTool = step.try_into()?;
+
+    // the step must be marked before publishing, otherwise a fast tool could answer before
+    // the result can be correlated with it
+    let message_id = Uuid::new_v4();
+    let timeout = state.config.tool_timeout(&requested_tool.procedure);
+    let deadline = Utc::now() + timeout;
+    if !job::controller::mark_step_running(step_id, message_id, deadline, state).await? {
+        info!(step = ?step_id, "Not publishing a step that is no longer queued");
+        return Ok(false);
+    }
+
+    let priority = job
+        .priority
+        .clamp(0, state.config.rabbitmq_max_priority as i16) as u8;
+
+    let published = send_request_to_rabbitmq(
+        message_id,
+        &image_input_path,
+        &image_output_path,
+        &requested_tool,
+        timeout,
+        priority,
+        state,
+    )
+    .await;
+
+    if let Err(e) = published {
+        // nothing will answer the request, so the step is published again on the next schedule
+        job::controller::mark_step_unpublished(step_id, message_id, state).await?;
+        return Err(e.into());
+    }
+
+    debug!(
+        job = ?job.id,
+        image = ?original_image_id,
+        ?message_id,
+        priority,
+        "Published job step {}", step_id
+    );
+
+    Ok(true)
+}
+
+/// Reads the metadata of the input of a step. Preview jobs run on the proxies, so their size is
+/// scaled to the one of the original image, and a proxy takes the orientation of its original.
+async fn input_metadata(
+    step: &JobStep,
+    image_input_path: &Path,
+    job: &Job,
+    state: &AppState,
+) -> Result<ImageMetadata, AppError> {
+    let metadata = ImageMetadata::read(image_input_path, state).await?;
+    if !job.preview {
+        return Ok(metadata);
+    }
+
+    let image =
+        image::controller::get_original_image(job.project_id, step.original_image_id, state)
+            .await?
+            .ok_or(AppError::EntityNotFound)?;
+    let original = ImageMetadata::read(&image.get_uri(), state).await?;
+    let proxy_path = image::controller::get_proxy_image_uri(&image, state).await?;
+    let proxy = ImageMetadata::read(&proxy_path, state).await?;
+
+    let mut metadata = metadata.scale(original.width as f64 / proxy.width.max(1) as f64);
+    // the proxies are PNGs, which lose the EXIF orientation of the original
+    if image_input_path == proxy_path {
+        metadata.orientation = original.orientation;
+    }
+
+    Ok(metadata)
+}
+
+/// Completes a step whose condition didn't match, along with the following steps its condition
+/// skips, passing the input of the step through as their output.
+async fn skip_steps(
+    step: JobStep,
+    skip_following: u32,
+    image_input_path: &Path,
+    job: &Job,
+    state: &AppState,
+) -> Result<(), AppError> {
+    let mut steps = if skip_following > 0 {
+        let depth = skip_following.try_into().unwrap_or(i32::MAX);
+        job::controller::get_descendant_steps(step.id, depth, state).await?
+    } else {
+        vec![]
+    };
+    // the children only become ready once their parent is marked, so it goes last
+    steps.push(step);
+
+    for mut step in steps {
+        let image_output_path = step.get_output_uri(job);
+        state
+            .storage
+            .copy(image_input_path, &image_output_path)
             .await?;
 
-    state
-        .queued_tools
-        .insert(message_id, (tool_uuid, queued_image_apply_tool));
+        if !job::controller::mark_step_skipped(step.id, state).await? {
+            info!(step = ?step.id, "Not skipping a step that is no longer queued");
+            discard_step_output(&step, job, state).await?;
+            continue;
+        }
+
+        let mut transaction = state.db_pool.begin().await?;
+        if let Some(cache_key) = step.cache_key.as_deref().filter(|_| !job.preview) {
+            cache::store(cache_key, None, &mut transaction).await?;
+        }
+
+        step.skipped = true;
+        let step_result = save_step_result(&step, job, None, &mut transaction).await?;
+        transaction.commit().await?;
+
+        send_step_result(step_result, job, state).await;
+    }
 
     Ok(())
 }
 
+/// Whether a newer run of the tools of the same kind was started on the image of the step after
+/// the job.
+async fn is_superseded(step: &JobStep, job: &Job, state: &AppState) -> Result<bool, AppError> {
+    let current_generation = job::controller::get_current_generation(
+        job.project_id,
+        step.original_image_id,
+        job.preview,
+        state,
+    )
+    .await?;
+    Ok(current_generation.is_some_and(|current_generation| current_generation > job.generation))
+}
+
 pub async fn run_rabbit_mq_results_read_loop(mut consumer: RabbitMqConsumer, state: AppState) {
     loop {
-        let message = consumer.next_result_message().await;
-        let Ok(message) = message else {
-            error!("Failed to receive message: {:?}", message);
+        let message = consumer.next_delivery::<ResponseMessage>().await;
+        let Ok((message, acker)) = message else {
+            error!("Failed to receive message: {:?}", message.err());
             continue;
         };
-        let Some((_, (tool_uuid, queued_tool))) =
-            state.queued_tools.remove(&message.correlation_id)
-        else {
-            error!(
-                "Received a result for an unknown tool: {}",
-                message.correlation_id
-            );
-            continue;
+
+        let result = if state.live_previews.is_live_preview(&message.correlation_id) {
+            live_preview::handle_result_message(message, &state).await
+        } else {
+            handle_result_message(message, &state).await
         };
 
-        match message.status {
-            ResponseStatus::Success { output } => {
-                info!(message = ?message.message_id, ?output, "Received a success response");
-
-                let image_version = ImageVersion {
-                    id: queued_tool.new_image_uuid,
-                    original_image_id: queued_tool.original_image_uuid,
-                    project_id: queued_tool.project_id,
-                    tool_id: tool_uuid,
-                    text_result: (output.kind == Text).then_some(output.text).flatten(),
-                    created_at: Utc::now(),
-                };
-
-                // save the image version to the database
-                if let Err(e) = controller::save_image_version(&image_version, &state).await {
-                    error!("Failed to save image version to the database: {}", e);
-                }
-
-                let notification = ImageVersionWithUrl::from_image_version(image_version, &state);
-
-                if let Err(err) = websocket::send_ws_message(
-                    &state,
-                    queued_tool.project_id,
-                    queued_tool.user_id,
-                    notification,
-                )
-                .await
-                {
-                    error!("Failed to send message to websocket: {}", err);
-                }
-
-                // if there are more tools to apply, add the tool to the queue
-                if !queued_tool.missing_tools.is_empty() {
-                    let queued_tool = QueuedImageApplyTool::new_generate_output_uri(
-                        queued_tool.project_id,
-                        queued_tool.user_id,
-                        queued_tool.original_image_uuid,
-                        queued_tool.image_output_uri, // now the new input will be the output of the previous tool
-                        queued_tool.missing_tools,
-                        &state,
-                    );
-
-                    if let Err(e) = add_to_queue(queued_tool, &state).await {
-                        error!("Failed to add tool to queue: {}", e);
-                    }
-                }
+        // the result is only acked once it is saved, otherwise it goes to the dead-letter
+        // exchange and the step is retried once its deadline passes
+        let settled = match result {
+            Ok(()) => acker.ack().await,
+            Err(e) => {
+                error!("Failed to handle result message: {}", e);
+                acker.reject().await
             }
-            ResponseStatus::Error { error } => {
-                info!(message = ?message.message_id, ?error, "Received a error response");
-
-                if let Err(err) = websocket::send_ws_message(
-                    &state,
-                    queued_tool.project_id,
-                    queued_tool.user_id,
-                    json!({
-                        "error": error,
-                    }),
-                )
-                .await
-                {
-                    error!("Failed to send message to websocket: {}", err);
-                }
-                continue; // we can't apply the next tool if the current one failed
+        };
+
+        if let Err(e) = settled {
+            error!("Failed to settle result message: {}", e);
+        }
+
+        // the step is no longer in flight, and the children of the step may be ready
+        schedule(&state);
+    }
+}
+
+async fn handle_result_message(message: ResponseMessage, state: &AppState) -> Result<(), AppError> {
+    let Some(step) = job::controller::get_step_by_message_id(message.correlation_id, state).await?
+    else {
+        error!(
+            "Received a result for an unknown tool: {}",
+            message.correlation_id
+        );
+        return Ok(());
+    };
+
+    let job = job::controller::get_job(step.job_id, state)
+        .await?
+        .ok_or(AppError::EntityNotFound)?;
+
+    if is_superseded(&step, &job, state).await? {
+        info!(
+            step = ?step.id,
+            generation = job.generation,
+            "Rejecting a result from a superseded run"
+        );
+        job::controller::mark_step_superseded(step.id, state).await?;
+        discard_step_output(&step, &job, state).await?;
+        return Ok(());
+    }
+
+    match message.status {
+        ResponseStatus::Success { output } => {
+            info!(message = ?message.message_id, ?output, "Received a success response");
+
+            // the step, its cache entry and its version are saved together, so a failure leaves
+            // the step running to be retried
+            let mut transaction = state.db_pool.begin().await?;
+
+            if !job::controller::mark_step_succeeded(step.id, &message.metadata, &mut transaction)
+                .await?
+            {
+                transaction.rollback().await?;
+                info!(step = ?step.id, "Discarding a result for a step that is no longer running");
+                discard_step_output(&step, &job, state).await?;
+                return Ok(());
+            }
+
+            let text_result = (output.kind == Text).then_some(output.text).flatten();
+
+            if let Some(cache_key) = step.cache_key.as_deref().filter(|_| !job.preview) {
+                cache::store(cache_key, text_result.as_deref(), &mut transaction).await?;
             }
+
+            let step_result = save_step_result(&step, &job, text_result, &mut transaction).await?;
+            transaction.commit().await?;
+
+            send_step_result(step_result, &job, state).await;
+        }
+        ResponseStatus::Error { error } => {
+            info!(message = ?message.message_id, ?error, "Received a error response");
+
+            handle_step_error(
+                step,
+                job,
+                error,
+                Some(message.metadata.processing_time),
+                JobStepStatus::Failed,
+                state,
+            )
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The saved result of a succeeded step.
+enum StepResult {
+    Preview(PreviewVersion),
+    Image(ImageVersion),
+}
+
+/// Saves the result of a succeeded step, as a preview version for preview jobs and as an image
+/// version otherwise.
+async fn save_step_result(
+    step: &JobStep,
+    job: &Job,
+    text_result: Option<String>,
+    transaction: &mut PgConnection,
+) -> Result<StepResult, AppError> {
+    if job.preview {
+        let preview_version = PreviewVersion {
+            id: step.id,
+            original_image_id: step.original_image_id,
+            project_id: job.project_id,
+            tool_id: step.tool_id,
+            text_result,
+            skipped: step.skipped,
+            created_at: Utc::now(),
+        };
+
+        controller::save_preview_version(&preview_version, transaction).await?;
+        return Ok(StepResult::Preview(preview_version));
+    }
+
+    let image_version = ImageVersion {
+        id: step.id,
+        original_image_id: step.original_image_id,
+        project_id: job.project_id,
+        tool_id: step.tool_id,
+        text_result,
+        skipped: step.skipped,
+        created_at: Utc::now(),
+        cache_key: step.cache_key.clone(),
+    };
+
+    controller::save_image_version(&image_version, transaction).await?;
+    Ok(StepResult::Image(image_version))
+}
+
+/// Sends the saved result of a step to the user.
+async fn send_step_result(step_result: StepResult, job: &Job, state: &AppState) {
+    let notification = match step_result {
+        StepResult::Preview(preview_version) => json!({
+            "preview": PreviewVersionWithUrl::from_preview_version(preview_version, state),
+        }),
+        StepResult::Image(image_version) => {
+            json!(ImageVersionWithUrl::from_image_version(
+                image_version,
+                state
+            ))
+        }
+    };
+
+    if let Err(err) =
+        websocket::send_ws_message(state, job.project_id, job.user_id, notification).await
+    {
+        error!("Failed to send message to websocket: {}", err);
+    }
+}
+
+/// Deletes the output a tool wrote for a step whose result is discarded,
+/// unless it is a cached result other image versions may be using.
+async fn discard_step_output(step: &JobStep, job: &Job, state: &AppState) -> Result<(), AppError> {
+    if let Some(cache_key) = step.cache_key.as_deref().filter(|_| !job.preview) {
+        if cache::is_cached(cache_key, state).await? {
+            return Ok(());
         }
     }
+
+    let _ = state.storage.delete(&step.get_output_uri(job)).await;
+    Ok(())
+}
+
+/// Retries a step that didn't produce a result if the error allows it, otherwise marks it
+/// with `failed_status` and notifies the user.
+async fn handle_step_error(
+    step: JobStep,
+    job: Job,
+    error: ErrorObject,
+    processing_time: Option<f64>,
+    failed_status: JobStepStatus,
+    state: &AppState,
+) -> Result<(), AppError> {
+    if state
+        .config
+        .should_retry(&step.procedure, &error.code, step.attempts)
+    {
+        let backoff = state.config.retry_backoff(step.attempts);
+        let available_at = Utc::now() + backoff;
+        if !job::controller::mark_step_retrying(&step, &error, processing_time, available_at, state)
+            .await?
+        {
+            info!(step = ?step.id, "Discarding a result for a step that is no longer running");
+            return Ok(());
+        }
+
+        info!(step = ?step.id, attempt = step.attempts + 1, ?backoff, "Retrying step");
+
+        let state = state.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(backoff).await;
+            schedule(&state);
+        });
+
+        return Ok(());
+    }
+
+    // we can't apply the next tools if the current one failed
+    if !job::controller::mark_step_failed(&step, failed_status, &error, processing_time, state)
+        .await?
+    {
+        info!(step = ?step.id, "Discarding a result for a step that is no longer running");
+        return Ok(());
+    }
+
+    if let Err(err) = websocket::send_ws_message(
+        state,
+        job.project_id,
+        job.user_id,
+        json!({
+            "error": error,
+        }),
+    )
+    .await
+    {
+        error!("Failed to send message to websocket: {}", err);
+    }
+
+    Ok(())
+}
+
+/// Periodically times out the running steps whose tool didn't answer before their deadline.
+/// A late result of a timed out step is discarded once it arrives.
+pub async fn run_timeout_sweeper(state: AppState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(
+        state.config.picturas_timeout_sweep_interval_secs,
+    ));
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = sweep_timed_out_steps(&state).await {
+            error!("Failed to sweep timed out steps: {}", e);
+        }
+        live_preview::remove_expired(&state).await;
+
+        schedule(&state);
+    }
+}
+
+async fn sweep_timed_out_steps(state: &AppState) -> Result<(), AppError> {
+    for step in job::controller::get_timed_out_steps(state).await? {
+        let job = job::controller::get_job(step.job_id, state)
+            .await?
+            .ok_or(AppError::EntityNotFound)?;
+
+        let timeout = state.config.tool_timeout(&step.procedure);
+        info!(step = ?step.id, ?timeout, "Step timed out");
+
+        let error = ErrorObject {
+            code: "TIMEOUT".to_string(),
+            message: format!(
+                "The tool {} didn't answer within {} seconds",
+                step.procedure,
+                timeout.as_secs()
+            ),
+        };
+
+        handle_step_error(step, job, error, None, JobStepStatus::TimedOut, state).await?;
+    }
+
+    Ok(())
 }
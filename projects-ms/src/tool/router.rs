@@ -1,18 +1,21 @@
 use crate::error::AppError::Forbidden;
-use crate::error::Result;
+use crate::error::{AppError, Result};
 use crate::project::controller;
-use crate::tool::controller::ImageVersionWithUrl;
-use crate::tool::model::RequestedTool;
-use crate::tool::websocket;
+use crate::signed_url::DownloadAccess;
+use crate::tool::controller::{ImageVersionWithUrl, PreviewVersionWithUrl};
+use crate::tool::export::ExportSelection;
+use crate::tool::model::{PipelineDocument, RequestedTool};
+use crate::tool::{catalogue, live_preview, websocket};
 use crate::user::AccessTokenClaims;
 use crate::{image, tool, AppState};
 use axum::body::Bytes;
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, State};
 use axum::http::{header, HeaderMap, HeaderValue};
 use axum::response::IntoResponse;
-use axum::routing::{get, post};
+use axum::routing::{get, patch, post, put};
 use axum::{debug_handler, Json, Router};
 use serde_json::json;
+use std::collections::HashMap;
 use uuid::Uuid;
 
 pub fn router(state: AppState) -> Router {
@@ -21,6 +24,21 @@ pub fn router(state: AppState) -> Router {
             "/projects/{project_id}/tools",
             get(get_tools).post(add_tool).put(put_tools),
         )
+        .route("/projects/{project_id}/tools/graph", get(get_tool_graph))
+        .route("/projects/{project_id}/tools/export", get(export_pipeline))
+        .route("/projects/{project_id}/tools/import", post(import_pipeline))
+        .route(
+            "/projects/{project_id}/tools/{tool_id}",
+            patch(update_tool).delete(delete_tool),
+        )
+        .route(
+            "/projects/{project_id}/tools/{tool_id}/parent",
+            put(set_tool_parent),
+        )
+        .route(
+            "/projects/{project_id}/tools/{tool_id}/position",
+            put(move_tool),
+        )
         .route("/projects/{project_id}/tools/apply", post(apply_tools))
         .route(
             "/projects/{project_id}/tools/images",
@@ -30,12 +48,22 @@ pub fn router(state: AppState) -> Router {
             "/projects/{project_id}/tools/images/{image_version_id}",
             get(download_image_version),
         )
+        .route(
+            "/projects/{project_id}/tools/previews",
+            get(get_preview_versions),
+        )
+        .route(
+            "/projects/{project_id}/tools/previews/{preview_version_id}",
+            get(download_preview_version),
+        )
         .route(
             "/projects/{project_id}/tools/imageszip",
             get(download_image_versions_zip),
         )
         .with_state(state.clone())
-        .merge(websocket::router(state))
+        .merge(websocket::router(state.clone()))
+        .merge(live_preview::router(state.clone()))
+        .merge(catalogue::router(state))
 }
 
 #[debug_handler]
@@ -53,20 +81,152 @@ async fn get_tools(
         .map(Json))
 }
 
+#[debug_handler]
+async fn get_tool_graph(
+    Path(project_id): Path<Uuid>,
+    user: AccessTokenClaims,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse> {
+    if !controller::can_modify(project_id, user.sub, &state).await? {
+        return Err(Forbidden);
+    }
+
+    let graph = tool::controller::get_tool_graph(project_id, &state).await?;
+    let edges = graph.edges();
+
+    Ok(Json(json!({
+        "nodes": graph.nodes,
+        "edges": edges,
+    })))
+}
+
+#[derive(serde::Deserialize)]
+struct AddToolRequest {
+    #[serde(flatten)]
+    tool: RequestedTool,
+    /// The tool whose output the new tool takes as input, the last tool if none is given.
+    parent_id: Option<Uuid>,
+}
+
 #[debug_handler]
 async fn add_tool(
     Path(project_id): Path<Uuid>,
     user: AccessTokenClaims,
     State(state): State<AppState>,
-    Json(tool): Json<RequestedTool>,
+    Json(request): Json<AddToolRequest>,
 ) -> Result<impl IntoResponse> {
     if !controller::can_modify(project_id, user.sub, &state).await? {
         return Err(Forbidden);
     }
 
-    Ok(tool::controller::add_tool(project_id, tool, &state)
-        .await
-        .map(Json))
+    Ok(
+        tool::controller::add_tool(project_id, request.tool, request.parent_id, &state)
+            .await
+            .map(Json),
+    )
+}
+
+#[derive(serde::Deserialize)]
+struct SetToolParentRequest {
+    /// The tool whose output the tool takes as input, the original image if none is given.
+    parent_id: Option<Uuid>,
+}
+
+#[debug_handler]
+async fn set_tool_parent(
+    Path((project_id, tool_id)): Path<(Uuid, Uuid)>,
+    user: AccessTokenClaims,
+    State(state): State<AppState>,
+    Json(request): Json<SetToolParentRequest>,
+) -> Result<impl IntoResponse> {
+    if !controller::can_modify(project_id, user.sub, &state).await? {
+        return Err(Forbidden);
+    }
+
+    let graph =
+        tool::controller::set_tool_parent(project_id, tool_id, request.parent_id, &state).await?;
+    let edges = graph.edges();
+
+    Ok(Json(json!({
+        "nodes": graph.nodes,
+        "edges": edges,
+    })))
+}
+
+#[derive(serde::Deserialize)]
+struct UpdateToolRequest {
+    /// The parameters to change, the other parameters of the tool are kept.
+    #[serde(default)]
+    parameters: HashMap<String, serde_json::Value>,
+    /// Enables or disables the tool, a disabled tool is kept but bypassed when applying the tools.
+    enabled: Option<bool>,
+}
+
+#[debug_handler]
+async fn update_tool(
+    Path((project_id, tool_id)): Path<(Uuid, Uuid)>,
+    user: AccessTokenClaims,
+    State(state): State<AppState>,
+    Json(request): Json<UpdateToolRequest>,
+) -> Result<impl IntoResponse> {
+    if !controller::can_modify(project_id, user.sub, &state).await? {
+        return Err(Forbidden);
+    }
+
+    let tool = tool::controller::update_tool(
+        project_id,
+        tool_id,
+        request.parameters,
+        request.enabled,
+        &state,
+    )
+    .await?;
+    Ok(Json(tool))
+}
+
+#[debug_handler]
+async fn delete_tool(
+    Path((project_id, tool_id)): Path<(Uuid, Uuid)>,
+    user: AccessTokenClaims,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse> {
+    if !controller::can_modify(project_id, user.sub, &state).await? {
+        return Err(Forbidden);
+    }
+
+    let graph = tool::controller::delete_tool(project_id, tool_id, &state).await?;
+    let edges = graph.edges();
+
+    Ok(Json(json!({
+        "nodes": graph.nodes,
+        "edges": edges,
+    })))
+}
+
+#[derive(serde::Deserialize)]
+struct MoveToolRequest {
+    /// The new position of the tool, 1 being the first tool.
+    position: i32,
+}
+
+#[debug_handler]
+async fn move_tool(
+    Path((project_id, tool_id)): Path<(Uuid, Uuid)>,
+    user: AccessTokenClaims,
+    State(state): State<AppState>,
+    Json(request): Json<MoveToolRequest>,
+) -> Result<impl IntoResponse> {
+    if !controller::can_modify(project_id, user.sub, &state).await? {
+        return Err(Forbidden);
+    }
+
+    let graph = tool::controller::move_tool(project_id, tool_id, request.position, &state).await?;
+    let edges = graph.edges();
+
+    Ok(Json(json!({
+        "nodes": graph.nodes,
+        "edges": edges,
+    })))
 }
 
 #[debug_handler]
@@ -85,9 +245,94 @@ async fn put_tools(
         .map(Json))
 }
 
+#[derive(serde::Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum PipelineFormat {
+    #[default]
+    Json,
+    Yaml,
+}
+
+#[derive(serde::Deserialize)]
+struct ExportPipelineQuery {
+    #[serde(default)]
+    format: PipelineFormat,
+}
+
+#[debug_handler]
+async fn export_pipeline(
+    Path(project_id): Path<Uuid>,
+    Query(query): Query<ExportPipelineQuery>,
+    user: AccessTokenClaims,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse> {
+    if !controller::can_modify(project_id, user.sub, &state).await? {
+        return Err(Forbidden);
+    }
+
+    let document = tool::controller::export_pipeline(project_id, &state).await?;
+
+    let (body, content_type, disposition) = match query.format {
+        PipelineFormat::Json => (
+            serde_json::to_string_pretty(&document)?,
+            "application/json",
+            "attachment; filename=\"pipeline.json\"",
+        ),
+        PipelineFormat::Yaml => (
+            serde_yaml::to_string(&document).map_err(|_| AppError::InternalError)?,
+            "application/yaml",
+            "attachment; filename=\"pipeline.yaml\"",
+        ),
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        HeaderValue::from_static(disposition),
+    );
+
+    Ok((headers, body))
+}
+
+#[debug_handler]
+async fn import_pipeline(
+    Path(project_id): Path<Uuid>,
+    user: AccessTokenClaims,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<impl IntoResponse> {
+    if !controller::can_modify(project_id, user.sub, &state).await? {
+        return Err(Forbidden);
+    }
+
+    let is_yaml = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.contains("yaml"));
+
+    let document: PipelineDocument = if is_yaml {
+        serde_yaml::from_slice(&body)
+            .map_err(|err| AppError::InvalidPipelineDocument(err.to_string()))?
+    } else {
+        serde_json::from_slice(&body)
+            .map_err(|err| AppError::InvalidPipelineDocument(err.to_string()))?
+    };
+
+    Ok(
+        tool::controller::import_pipeline(project_id, document, &state)
+            .await
+            .map(Json),
+    )
+}
+
 #[derive(serde::Deserialize)]
 struct ApplyToolsRequest {
     filter_images: Option<Vec<Uuid>>,
+    /// Runs the tools on a downscaled proxy of the images, without replacing the image versions.
+    #[serde(default)]
+    preview: bool,
 }
 
 #[debug_handler]
@@ -107,13 +352,23 @@ async fn apply_tools(
         images.retain(|image| filter_images.contains(&image.id));
     }
 
-    tool::controller::apply_added_tools(project_id, user.sub, &images, &state).await?;
+    let priority = user.role.priority(&state.config);
+    let job = tool::controller::apply_added_tools(
+        project_id,
+        user.sub,
+        priority,
+        image_ids.preview,
+        &images,
+        &state,
+    )
+    .await?;
 
     let image_ids = images.iter().map(|image| image.id).collect::<Vec<_>>();
 
     Ok(Json(json!({
+        "job_id": job.id,
         "image_ids": image_ids,
-        "message": "Hook to websocket to get realtime results",
+        "message": "Hook to websocket or poll the job to get realtime results",
     })))
 }
 
@@ -139,6 +394,28 @@ async fn get_image_versions(
 #[debug_handler]
 async fn download_image_version(
     Path((project_id, image_version_id)): Path<(Uuid, Uuid)>,
+    access: DownloadAccess,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse> {
+    if !access
+        .can_download(project_id, image_version_id, &state)
+        .await?
+    {
+        return Err(Forbidden);
+    }
+
+    let image_bytes =
+        tool::controller::load_image_version(project_id, image_version_id, &state).await?;
+
+    Ok((
+        [(header::CONTENT_TYPE, HeaderValue::from_static("image/png"))],
+        image_bytes,
+    ))
+}
+
+#[debug_handler]
+async fn get_preview_versions(
+    Path(project_id): Path<Uuid>,
     user: AccessTokenClaims,
     State(state): State<AppState>,
 ) -> Result<impl IntoResponse> {
@@ -146,8 +423,30 @@ async fn download_image_version(
         return Err(Forbidden);
     }
 
+    let previews: Vec<_> = tool::controller::get_preview_versions(project_id, &state)
+        .await?
+        .into_iter()
+        .map(|preview_version| PreviewVersionWithUrl::from_preview_version(preview_version, &state))
+        .collect();
+
+    Ok(Json(previews))
+}
+
+#[debug_handler]
+async fn download_preview_version(
+    Path((project_id, preview_version_id)): Path<(Uuid, Uuid)>,
+    access: DownloadAccess,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse> {
+    if !access
+        .can_download(project_id, preview_version_id, &state)
+        .await?
+    {
+        return Err(Forbidden);
+    }
+
     let image_bytes =
-        tool::controller::load_image_version(project_id, image_version_id, &state).await?;
+        tool::controller::load_preview_version(project_id, preview_version_id, &state).await?;
 
     Ok((
         [(header::CONTENT_TYPE, HeaderValue::from_static("image/png"))],
@@ -155,24 +454,42 @@ async fn download_image_version(
     ))
 }
 
+#[derive(serde::Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum ExportSteps {
+    /// The results of the last tools of the pipeline.
+    #[default]
+    Final,
+    /// The results of every tool of the pipeline.
+    All,
+}
+
 #[derive(serde::Deserialize)]
-struct DownloadImageVersionsZipRequest {
-    tool_id: Uuid,
+struct DownloadImageVersionsZipQuery {
+    #[serde(default)]
+    steps: ExportSteps,
+    /// Exports the results of a single tool instead.
+    tool_id: Option<Uuid>,
 }
 
 #[debug_handler]
 async fn download_image_versions_zip(
     Path(project_id): Path<Uuid>,
+    Query(query): Query<DownloadImageVersionsZipQuery>,
     user: AccessTokenClaims,
     State(state): State<AppState>,
-    Json(body): Json<DownloadImageVersionsZipRequest>,
 ) -> Result<impl IntoResponse> {
     if !controller::can_modify(project_id, user.sub, &state).await? {
         return Err(Forbidden);
     }
 
-    let zip_bytes =
-        tool::controller::load_image_versions_zip(project_id, body.tool_id, &state).await?;
+    let selection = match (query.tool_id, query.steps) {
+        (Some(tool_id), _) => ExportSelection::Tool(tool_id),
+        (None, ExportSteps::Final) => ExportSelection::Final,
+        (None, ExportSteps::All) => ExportSelection::All,
+    };
+
+    let zip_body = tool::controller::load_image_versions_zip(project_id, selection, &state).await?;
 
     let mut headers = HeaderMap::new();
     headers.insert(
@@ -184,5 +501,5 @@ async fn download_image_versions_zip(
         HeaderValue::from_static("attachment; filename=\"images.zip\""),
     );
 
-    Ok((headers, Bytes::from(zip_bytes)))
+    Ok((headers, zip_body))
 }
@@ -0,0 +1,311 @@
+use crate::tool::catalogue;
+use crate::tool::model::RequestedTool;
+use crate::AppState;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+
+/// The errors of a request by the path of the field they are about, e.g.
+/// `{"parameters.radius": [{"code": "type", "message": "...", "params": {...}}]}`.
+#[derive(Debug, Default, Serialize)]
+#[serde(transparent)]
+pub struct ValidationErrors(BTreeMap<String, Vec<FieldError>>);
+
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    pub code: &'static str,
+    pub message: String,
+    pub params: Value,
+}
+
+impl ValidationErrors {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn add(&mut self, field: String, code: &'static str, message: String, params: Value) {
+        self.0.entry(field).or_default().push(FieldError {
+            code,
+            message,
+            params,
+        });
+    }
+
+    fn merge(&mut self, prefix: &str, other: ValidationErrors) {
+        for (field, errors) in other.0 {
+            self.0
+                .entry(format!("{prefix}.{field}"))
+                .or_default()
+                .extend(errors);
+        }
+    }
+}
+
+impl std::fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fields: Vec<&str> = self.0.keys().map(String::as_str).collect();
+        write!(f, "invalid fields: {}", fields.join(", "))
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+/// Checks that one of the available tools handles the procedure and, if the tool announced the
+/// schema of its parameters, that the parameters match it.
+pub fn validate_tool(tool: &RequestedTool, state: &AppState) -> Result<(), ValidationErrors> {
+    let mut errors = ValidationErrors::default();
+
+    let is_available = state
+        .config
+        .picturas_available_tools
+        .iter()
+        .any(|available_tool| available_tool.name == tool.procedure);
+    if !is_available {
+        errors.add(
+            "procedure".to_string(),
+            "unknown_procedure",
+            format!("no available tool handles the procedure {}", tool.procedure),
+            json!({ "value": tool.procedure }),
+        );
+        return Err(errors);
+    }
+
+    // some tools never announce themselves, and the catalogue is empty until the tools announce
+    // themselves again after a restart, so the parameters can't always be checked
+    if let Some(entry) = catalogue::get_tool(&tool.procedure, state) {
+        let parameters = Value::Object(Map::from_iter(tool.parameters.clone()));
+        validate_value(
+            &parameters,
+            &entry.parameters_schema,
+            "parameters",
+            &mut errors,
+        );
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Validates every tool, the errors of each tool under `tools[<index>]`.
+pub fn validate_tools<'a>(
+    tools: impl IntoIterator<Item = &'a RequestedTool>,
+    state: &AppState,
+) -> Result<(), ValidationErrors> {
+    let mut errors = ValidationErrors::default();
+
+    for (index, tool) in tools.into_iter().enumerate() {
+        if let Err(tool_errors) = validate_tool(tool, state) {
+            errors.merge(&format!("tools[{index}]"), tool_errors);
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Validates a value against the subset of JSON Schema the tools use to describe their
+/// parameters: `type`, `properties`, `required`, `additionalProperties`, `minimum`, `maximum`,
+/// `items`, `prefixItems`, `minItems` and `maxItems`.
+fn validate_value(value: &Value, schema: &Value, path: &str, errors: &mut ValidationErrors) {
+    if let Some(expected) = schema.get("type").and_then(Value::as_str) {
+        if !has_type(value, expected) {
+            errors.add(
+                path.to_string(),
+                "type",
+                format!("expected a value of type {expected}"),
+                json!({ "expected": expected, "value": value }),
+            );
+            return;
+        }
+    }
+
+    if let Some(number) = value.as_f64() {
+        if let Some(minimum) = schema.get("minimum").and_then(Value::as_f64) {
+            if number < minimum {
+                errors.add(
+                    path.to_string(),
+                    "minimum",
+                    format!("must be at least {minimum}"),
+                    json!({ "min": minimum, "value": value }),
+                );
+            }
+        }
+        if let Some(maximum) = schema.get("maximum").and_then(Value::as_f64) {
+            if number > maximum {
+                errors.add(
+                    path.to_string(),
+                    "maximum",
+                    format!("must be at most {maximum}"),
+                    json!({ "max": maximum, "value": value }),
+                );
+            }
+        }
+    }
+
+    if let Some(object) = value.as_object() {
+        let properties = schema.get("properties").and_then(Value::as_object);
+
+        for required in schema
+            .get("required")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+        {
+            if !object.contains_key(required) {
+                errors.add(
+                    format!("{path}.{required}"),
+                    "required",
+                    "is required".to_string(),
+                    json!({}),
+                );
+            }
+        }
+
+        for (key, property) in object {
+            match properties.and_then(|properties| properties.get(key)) {
+                Some(property_schema) => {
+                    validate_value(property, property_schema, &format!("{path}.{key}"), errors)
+                }
+                None if schema.get("additionalProperties") == Some(&Value::Bool(false)) => {
+                    errors.add(
+                        format!("{path}.{key}"),
+                        "unknown_field",
+                        "isn't a parameter of the procedure".to_string(),
+                        json!({ "value": property }),
+                    );
+                }
+                None => {}
+            }
+        }
+    }
+
+    if let Some(array) = value.as_array() {
+        let length = array.len() as u64;
+        if let Some(min_items) = schema.get("minItems").and_then(Value::as_u64) {
+            if length < min_items {
+                errors.add(
+                    path.to_string(),
+                    "min_items",
+                    format!("must have at least {min_items} items"),
+                    json!({ "min": min_items, "value": value }),
+                );
+            }
+        }
+        if let Some(max_items) = schema.get("maxItems").and_then(Value::as_u64) {
+            if length > max_items {
+                errors.add(
+                    path.to_string(),
+                    "max_items",
+                    format!("must have at most {max_items} items"),
+                    json!({ "max": max_items, "value": value }),
+                );
+            }
+        }
+
+        let prefix_items = schema
+            .get("prefixItems")
+            .and_then(Value::as_array)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        for (index, item) in array.iter().enumerate() {
+            let item_schema = prefix_items.get(index).or_else(|| schema.get("items"));
+            if let Some(item_schema) = item_schema {
+                validate_value(item, item_schema, &format!("{path}[{index}]"), errors);
+            }
+        }
+    }
+}
+
+fn has_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        "number" => value.is_number(),
+        // the tools don't accept integers written as floats, e.g. `5.0`
+        "integer" => value.is_i64() || value.is_u64(),
+        // types this subset doesn't know about aren't checked
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tool::validation::{validate_value, ValidationErrors};
+    use serde_json::{json, Value};
+
+    fn validate(value: Value, schema: Value) -> Value {
+        let mut errors = ValidationErrors::default();
+        validate_value(&value, &schema, "parameters", &mut errors);
+        serde_json::to_value(errors).unwrap()
+    }
+
+    #[test]
+    fn test_valid_parameters() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "radius": {"type": "integer", "minimum": 1, "maximum": 10},
+                "color": {"type": "array", "items": {"type": "integer"}, "minItems": 3},
+            },
+            "required": ["radius"],
+        });
+
+        let errors = validate(json!({"radius": 5, "color": [0, 0, 255]}), schema);
+        assert_eq!(errors, json!({}));
+    }
+
+    #[test]
+    fn test_invalid_parameters() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "radius": {"type": "integer", "minimum": 1},
+                "angle": {"type": "number", "maximum": 360},
+            },
+            "required": ["radius", "angle"],
+            "additionalProperties": false,
+        });
+
+        let errors = validate(json!({"radius": 5.0, "sigma": 2}), schema.clone());
+        assert_eq!(errors["parameters.radius"][0]["code"], "type");
+        assert_eq!(
+            errors["parameters.radius"][0]["params"],
+            json!({"expected": "integer", "value": 5.0})
+        );
+        assert_eq!(errors["parameters.angle"][0]["code"], "required");
+        assert_eq!(errors["parameters.sigma"][0]["code"], "unknown_field");
+
+        let errors = validate(json!({"radius": 0, "angle": 400}), schema);
+        assert_eq!(
+            errors["parameters.radius"][0]["params"],
+            json!({"min": 1.0, "value": 0})
+        );
+        assert_eq!(errors["parameters.angle"][0]["code"], "maximum");
+    }
+
+    #[test]
+    fn test_invalid_items() {
+        let schema = json!({
+            "type": "array",
+            "prefixItems": [{"type": "string"}],
+            "items": {"type": "integer"},
+            "maxItems": 3,
+        });
+
+        let errors = validate(json!([1, 2, "3", 4]), schema);
+        assert_eq!(errors["parameters"][0]["code"], "max_items");
+        assert_eq!(errors["parameters[0]"][0]["code"], "type");
+        assert_eq!(errors["parameters[2]"][0]["code"], "type");
+        assert!(errors.get("parameters[1]").is_none());
+    }
+}
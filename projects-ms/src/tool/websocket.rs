@@ -1,3 +1,7 @@
+use crate::error::AppError::Forbidden;
+use crate::project::controller::can_modify;
+use crate::tool::live_preview;
+use crate::tool::live_preview::LivePreviewRequest;
 use crate::user::AccessTokenClaims;
 use crate::AppState;
 use axum::extract::ws::{Message, WebSocket};
@@ -22,8 +26,12 @@ async fn ws_handler(
     user: AccessTokenClaims,
     State(state): State<AppState>,
     Path(project_uuid): Path<Uuid>,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_socket(socket, project_uuid, user, state))
+) -> crate::error::Result<impl IntoResponse> {
+    if !can_modify(project_uuid, user.sub, &state).await? {
+        return Err(Forbidden);
+    }
+
+    Ok(ws.on_upgrade(move |socket| handle_socket(socket, project_uuid, user, state)))
 }
 
 async fn handle_socket(
@@ -41,17 +49,50 @@ async fn handle_socket(
 
     register_ws_client(&state, project_uuid, user.sub, sender).await;
 
-    while let Some(message) = receiver.recv().await {
-        if let Err(err) = socket.send(message).await {
-            error!(?err, "Error sending message to WS client");
-            break;
+    loop {
+        tokio::select! {
+            message = receiver.recv() => {
+                let Some(message) = message else {
+                    break;
+                };
+
+                if let Err(err) = socket.send(message).await {
+                    error!(?err, "Error sending message to WS client");
+                    break;
+                }
+            }
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    handle_client_message(&text, project_uuid, &user, &state).await;
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            }
         }
     }
 
     unregister_ws_client(&state, project_uuid, user.sub).await;
+    live_preview::remove_client(project_uuid, user.sub, &state).await;
     info!("WS client disconnected");
 }
 
+/// Handles a message sent by the client, currently only live preview requests.
+async fn handle_client_message(
+    text: &str,
+    project_uuid: Uuid,
+    user: &AccessTokenClaims,
+    state: &AppState,
+) {
+    let result = match serde_json::from_str::<LivePreviewRequest>(text) {
+        Ok(request) => live_preview::request_live_preview(project_uuid, user, request, state).await,
+        Err(err) => Err(err.into()),
+    };
+
+    if let Err(err) = result {
+        live_preview::send_live_preview_error(project_uuid, user.sub, err, state).await;
+    }
+}
+
 pub async fn register_ws_client(
     state: &AppState,
     project_uuid: Uuid,
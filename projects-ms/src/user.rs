@@ -1,3 +1,4 @@
+use crate::config::Config;
 use crate::error::AppError;
 use crate::error::Result;
 use crate::state::AppState;
@@ -29,9 +30,30 @@ pub struct AccessTokenClaims {
     pub sub: Uuid,
     pub name: String,
     pub email: String,
+    #[serde(default)]
+    pub role: UserRole,
     pub exp: i64,
 }
 
+/// The subscription tier of a user.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UserRole {
+    #[default]
+    Default,
+    Premium,
+}
+
+impl UserRole {
+    /// The priority the tool requests of a user with this role are published with.
+    pub fn priority(&self, config: &Config) -> u8 {
+        match self {
+            UserRole::Default => 0,
+            UserRole::Premium => config.picturas_premium_priority,
+        }
+    }
+}
+
 pub fn decode_access_token(state: &AppState, token: &str) -> Result<AccessTokenClaims> {
     let key = &state.config.access_token_public_key;
     let token: AccessTokenClaims =
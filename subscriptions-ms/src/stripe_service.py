@@ -24,13 +24,17 @@ PRICE_OBJECT = os.getenv('PRICE_OBJECT')
 SUCCESS_URL = os.getenv('SUCCESS_URL')
 CANCEL_URL = os.getenv('CANCEL_URL')
 ACCESS_TOKEN_PUBLIC_KEY = os.getenv('ACCESS_TOKEN_PUBLIC_KEY')
+INTERNAL_TOKEN = os.getenv('INTERNAL_TOKEN')
 
 with open(ACCESS_TOKEN_PUBLIC_KEY, "r") as file:
     pub_key = file.read().strip()
 
+with open(INTERNAL_TOKEN, "r") as file:
+    internal_token = file.read().strip()
+
 # POST to the users endpoint with premium role
 async def notif_users(sub: Subscription):
-    user_id = sub.user_id
+    user_id = str(sub.user_id)
     end_date = sub.end_date.isoformat()
     if sub.status == 'active':
         role = 'premium'
@@ -41,13 +45,14 @@ async def notif_users(sub: Subscription):
     data = {
         'user_id': user_id,
         'role': role,
-        'expires_on': end_date
+        'expires_on': expires_on
     }
     logging.info(f'Notifying users service with data: {data}')
     
     try:
         async with httpx.AsyncClient() as client:
-            response = await client.post(USERS_ENDPOINT, json=data) # POST to the users endpoint
+            headers = {'X-Internal-Token': internal_token}
+            response = await client.post(USERS_ENDPOINT, json=data, headers=headers) # POST to the users endpoint
             response.raise_for_status()
             logging.info(f'Users service response: {response.json()}')
 
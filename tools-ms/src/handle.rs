@@ -1,26 +1,30 @@
 use crate::handle::HandleRequestError::{
-    ImageOpenError, ImageReadError, ImageSaveCreateFoldersError, ImageSaveError, ToolApplyError,
+    ImageEncodeError, ImageOpenError, ImageReadError, ImageSaveError, ToolApplyError,
 };
 use crate::message::RequestMessage;
+use crate::storage::{Storage, StorageError};
 use crate::tools;
-use std::path::PathBuf;
+use image::DynamicImage::ImageRgba8;
+use photon_rs::{helpers, PhotonImage};
+use std::io::Cursor;
+use std::sync::Arc;
 use thiserror::Error;
 use tracing::{info, instrument};
 
 #[derive(Debug)]
 pub enum HandleRequestResult {
-    Image(PathBuf),
+    Image(String),
     Text(String),
 }
 
 #[derive(Debug, Error)]
 pub enum HandleRequestError {
     #[error("Failed to save image: {0}")]
-    ImageSaveError(photon_rs::native::Error),
-    #[error("Failed to create folders: {0}")]
-    ImageSaveCreateFoldersError(tokio::io::Error),
+    ImageSaveError(StorageError),
+    #[error("Failed to encode image: {0}")]
+    ImageEncodeError(image::ImageError),
     #[error("Failed to open image: {0}")]
-    ImageOpenError(tokio::io::Error),
+    ImageOpenError(StorageError),
     #[error("Failed to read image: {0}")]
     ImageReadError(photon_rs::native::Error),
     #[error("Failed to apply tool: {0}")]
@@ -32,12 +36,11 @@ pub enum HandleRequestError {
 #[instrument(skip(request), fields(message_id = request.message_id))]
 pub async fn handle_request(
     request: RequestMessage,
+    storage: Arc<Storage>,
 ) -> Result<HandleRequestResult, HandleRequestError> {
     info!("Handling request");
-    let path = request.params.image_uris.input_image_uri;
-    let image_bytes = tokio::fs::read(&path.clone())
-        .await
-        .map_err(ImageOpenError)?;
+    let uri = request.params.image_uris.input_image_uri;
+    let image_bytes = storage.read(&uri).await.map_err(ImageOpenError)?;
     let image = photon_rs::native::open_image_from_bytes(&image_bytes).map_err(ImageReadError)?;
     info!(
         raw_pixels_len = image.get_raw_pixels().len(),
@@ -53,17 +56,15 @@ pub async fn handle_request(
 
     match result {
         tools::ToolApplyResult::Image(image) => {
-            if let Some(path) = request.params.image_uris.output_image_uri {
-                info!(path = path.to_string_lossy().as_ref(), "Saving image");
+            if let Some(uri) = request.params.image_uris.output_image_uri {
+                info!(uri, "Saving image");
 
-                if let Some(p) = path.parent() {
-                    tokio::fs::create_dir_all(p)
-                        .await
-                        .map_err(ImageSaveCreateFoldersError)?;
-                }
-
-                photon_rs::native::save_image(image, &path).map_err(ImageSaveError)?;
-                Ok(HandleRequestResult::Image(path))
+                let image_bytes = encode_png(&image).map_err(ImageEncodeError)?;
+                storage
+                    .write(&uri, &image_bytes)
+                    .await
+                    .map_err(ImageSaveError)?;
+                Ok(HandleRequestResult::Image(uri))
             } else {
                 Err(HandleRequestError::MissingOutputPath)
             }
@@ -71,3 +72,10 @@ pub async fn handle_request(
         tools::ToolApplyResult::Text(text) => Ok(HandleRequestResult::Text(text)),
     }
 }
+
+fn encode_png(image: &PhotonImage) -> Result<Vec<u8>, image::ImageError> {
+    let img = ImageRgba8(helpers::dyn_image_from_raw(image).to_rgba8());
+    let mut buffer = vec![];
+    img.write_to(&mut Cursor::new(&mut buffer), image::ImageOutputFormat::Png)?;
+    Ok(buffer)
+}
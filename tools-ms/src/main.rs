@@ -1,3 +1,4 @@
+use crate::storage::Storage;
 use lapin::{Channel, Connection};
 use serde::Deserialize;
 use serde_inline_default::serde_inline_default;
@@ -7,6 +8,7 @@ use tracing::info;
 mod handle;
 mod message;
 mod message_queue;
+mod storage;
 mod tools;
 // const CONSUMER_NAMES: [&str; 8] = [
 //     "crop",
@@ -23,6 +25,7 @@ mod tools;
 struct State {
     channel: Channel,
     config: Config,
+    storage: Arc<Storage>,
 }
 
 #[serde_inline_default]
@@ -45,6 +48,17 @@ struct Config {
     picturas_results_exchange: String,
     #[serde_inline_default("results".into())]
     picturas_results_routing_key: String,
+    #[serde_inline_default("picturas.tools.control".into())]
+    picturas_control_exchange: String,
+    #[serde_inline_default(30)]
+    picturas_announce_interval_secs: u64,
+    /// The endpoint of the S3-compatible storage of `s3://` URIs, e.g. `http://minio:9000`,
+    /// the one of AWS if none is given.
+    s3_endpoint: Option<String>,
+    #[serde_inline_default("us-east-1".into())]
+    s3_region: String,
+    s3_access_key: Option<String>,
+    s3_secret_key: Option<String>,
 }
 
 #[tokio::main]
@@ -71,9 +85,12 @@ async fn main() {
 
     info!("Created channel with prefetch count of {concurrent_requests}");
 
+    let storage = Storage::new(&config).expect("Failed to configure the storage");
+
     let state = Arc::new(State {
         channel: channel.clone(),
         config,
+        storage: Arc::new(storage),
     });
 
     let mut join_set = tokio::task::JoinSet::new();
@@ -84,6 +101,8 @@ async fn main() {
         join_set.spawn(message_queue::run_rabbitmq_queue(consumer, state.clone()));
     }
 
+    join_set.spawn(message_queue::run_announcements(state.clone()));
+
     tokio::select! {
         _ = join_set.join_next() => {}
         _ = tokio::signal::ctrl_c() => {}
@@ -2,7 +2,6 @@ use crate::tools::Tool;
 use chrono::{DateTime, Utc};
 use serde::ser::SerializeMap;
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
-use std::path::PathBuf;
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -22,9 +21,9 @@ pub struct ToolParts {
 #[derive(Deserialize, Serialize, Debug)]
 pub struct ImageUriParams {
     #[serde(rename = "inputImageURI")]
-    pub input_image_uri: PathBuf,
+    pub input_image_uri: String,
     #[serde(rename = "outputImageURI", skip_serializing_if = "Option::is_none")]
-    pub output_image_uri: Option<PathBuf>,
+    pub output_image_uri: Option<String>,
 }
 
 #[derive(Serialize, Debug)]
@@ -74,7 +73,7 @@ impl Serialize for ResponseMessageStatus {
 pub enum OutputType {
     Image {
         #[serde(rename = "imageURI")]
-        image_uri: PathBuf,
+        image_uri: String,
     },
     Text {
         text: String,
@@ -88,6 +87,24 @@ pub struct Metadata {
     pub microservice: String,
 }
 
+/// Sent to the control exchange so the services building pipelines know which procedures are
+/// available and the parameters they take.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AnnouncementMessage {
+    pub message_id: String,
+    pub timestamp: DateTime<Utc>,
+    pub microservice: String,
+    pub tools: Vec<ToolDescription>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolDescription {
+    pub procedure: String,
+    pub parameters_schema: serde_json::Value,
+}
+
 // Custom deserialize is needed because serde_json can't merge two "parameters" objects into one
 impl<'de> Deserialize<'de> for ToolParts {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
@@ -139,8 +156,6 @@ impl<'de> Deserialize<'de> for ToolParts {
 #[cfg(test)]
 mod tests {
     use crate::message::{ErrorDetails, OutputType, RequestMessage, ResponseMessageStatus};
-    use std::path::PathBuf;
-    use std::str::FromStr;
     // use chrono::{DateTime, Utc};
 
     #[test]
@@ -162,7 +177,7 @@ mod tests {
         // assert_eq!(input.timestamp, "2024-11-01T12:00:00Z".parse::<DateTime<Utc>>().unwrap());
         assert_eq!(
             input.params.image_uris.input_image_uri,
-            PathBuf::from_str("images/request-1-out.jpg").unwrap()
+            "images/request-1-out.jpg"
         );
         assert_eq!(
             input.params.image_uris.output_image_uri,
@@ -1,18 +1,22 @@
 use crate::handle::{HandleRequestError, HandleRequestResult};
 use crate::message::{
-    ErrorDetails, Metadata, OutputType, RequestMessage, ResponseMessage, ResponseMessageStatus,
+    AnnouncementMessage, ErrorDetails, Metadata, OutputType, RequestMessage, ResponseMessage,
+    ResponseMessageStatus, ToolDescription,
 };
-use crate::{handle, State};
+use crate::{handle, tools, State};
 use anyhow::Context;
 use chrono::Utc;
 use futures_util::StreamExt;
 use lapin::message::Delivery;
-use lapin::options::{BasicAckOptions, BasicConsumeOptions, BasicPublishOptions, BasicQosOptions};
+use lapin::options::{
+    BasicAckOptions, BasicConsumeOptions, BasicNackOptions, BasicPublishOptions, BasicQosOptions,
+    ExchangeDeclareOptions,
+};
 use lapin::publisher_confirm::PublisherConfirm;
 use lapin::types::{FieldTable, ShortUInt};
-use lapin::{BasicProperties, Channel, Connection, Consumer};
+use lapin::{BasicProperties, Channel, Connection, Consumer, ExchangeKind};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use tracing::{debug, error, info};
 use uuid::Uuid;
 
@@ -30,9 +34,7 @@ impl From<Result<HandleRequestResult, HandleRequestError>> for ResponseMessageSt
             Err(request_error) => {
                 let code = match request_error {
                     HandleRequestError::ImageSaveError { .. } => "IMAGE_SAVE_ERROR",
-                    HandleRequestError::ImageSaveCreateFoldersError { .. } => {
-                        "IMAGE_SAVE_CREATE_FOLDERS_ERROR"
-                    }
+                    HandleRequestError::ImageEncodeError { .. } => "IMAGE_ENCODE_ERROR",
                     HandleRequestError::ImageOpenError { .. } => "IMAGE_OPEN_ERROR",
                     HandleRequestError::ToolApplyError { .. } => "TOOL_APPLY_ERROR",
                     HandleRequestError::MissingOutputPath => "MISSING_OUTPUT_PATH",
@@ -66,13 +68,25 @@ async fn publish_response(
 async fn handle_rabbitmq_delivery(delivery: Delivery, state: &State) -> anyhow::Result<()> {
     let instant = Instant::now();
 
-    let request: RequestMessage =
-        serde_json::from_slice(&delivery.data).context("Failed to deserialize request")?;
+    let request: RequestMessage = match serde_json::from_slice(&delivery.data) {
+        Ok(request) => request,
+        Err(error) => {
+            // the request will never be readable, let the broker send it to the dead-letter exchange
+            delivery
+                .nack(BasicNackOptions {
+                    requeue: false,
+                    ..Default::default()
+                })
+                .await
+                .context("Failed to nack delivery")?;
+            return Err(error).context("Failed to deserialize request");
+        }
+    };
 
     info!("Received request: {request:?}");
     let message_id = request.message_id.clone();
 
-    let result = tokio::task::spawn(handle::handle_request(request)).await?;
+    let result = tokio::task::spawn(handle::handle_request(request, state.storage.clone())).await?;
 
     match &result {
         Ok(result) => debug!(result = ?result, "Request handled successfully"),
@@ -108,6 +122,81 @@ async fn handle_rabbitmq_delivery(delivery: Delivery, state: &State) -> anyhow::
         .context("Failed to ack delivery")
 }
 
+/// Announces the available procedures to the control exchange, periodically so services that
+/// start later also get them.
+pub async fn run_announcements(state: Arc<State>) {
+    let exchange = &state.config.picturas_control_exchange;
+    if let Err(error) = state
+        .channel
+        .exchange_declare(
+            exchange,
+            ExchangeKind::Fanout,
+            ExchangeDeclareOptions {
+                durable: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await
+    {
+        error!("Failed to declare the control exchange {exchange:?}: {error}");
+        return;
+    }
+
+    let mut interval = tokio::time::interval(Duration::from_secs(
+        state.config.picturas_announce_interval_secs,
+    ));
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = publish_announcement(&state).await {
+            error!("Failed to announce the available tools: {e}");
+        }
+    }
+}
+
+async fn publish_announcement(state: &State) -> anyhow::Result<()> {
+    // only the procedures this service handles, of the ones it is configured to consume
+    let available_tools = &state.config.picturas_available_tools;
+    let tools = tools::PROCEDURES
+        .iter()
+        .filter(|procedure| available_tools.iter().any(|tool| tool == *procedure))
+        .filter_map(|procedure| {
+            Some(ToolDescription {
+                procedure: procedure.to_string(),
+                parameters_schema: tools::parameters_schema(procedure)?,
+            })
+        })
+        .collect();
+
+    let announcement = AnnouncementMessage {
+        message_id: Uuid::new_v4().to_string(),
+        timestamp: Utc::now(),
+        microservice: state.config.picturas_microservice_name.clone(),
+        tools,
+    };
+
+    debug!(announcement = ?announcement, "Announcing tools");
+
+    let announcement =
+        serde_json::to_vec(&announcement).context("Failed to serialize announcement")?;
+
+    state
+        .channel
+        .basic_publish(
+            &state.config.picturas_control_exchange,
+            "",
+            BasicPublishOptions::default(),
+            &announcement,
+            BasicProperties::default(),
+        )
+        .await
+        .context("Failed to publish announcement")?;
+
+    Ok(())
+}
+
 pub async fn run_rabbitmq_queue(mut consumer: Consumer, state: Arc<State>) {
     info!("Consumer {consumer:?} started");
 
@@ -0,0 +1,144 @@
+use crate::Config;
+use s3::creds::Credentials;
+use s3::{Bucket, Region};
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Reads and writes the images the requests point to, wherever the URI says they are stored.
+#[derive(Debug)]
+pub struct Storage {
+    s3_region: Region,
+    s3_credentials: Credentials,
+}
+
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("s3 error: {0}")]
+    S3(#[from] s3::error::S3Error),
+    #[error("s3 credentials error: {0}")]
+    S3Credentials(#[from] s3::creds::error::CredentialsError),
+    #[error("invalid uri: {0}")]
+    InvalidUri(String),
+    #[error("invalid s3 region: {0}")]
+    InvalidRegion(String),
+}
+
+/// Where a file is stored, e.g. `file:///images/a.png` or `s3://picturas/a.png`.
+/// A URI without a scheme is a path of the local filesystem.
+#[derive(Debug, PartialEq)]
+enum Location {
+    File(PathBuf),
+    S3 { bucket: String, key: String },
+}
+
+impl Location {
+    fn parse(uri: &str) -> Result<Self, StorageError> {
+        if let Some(path) = uri.strip_prefix("file://") {
+            return Ok(Location::File(path.into()));
+        }
+
+        if let Some(rest) = uri.strip_prefix("s3://") {
+            return match rest.split_once('/') {
+                Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() => Ok(Location::S3 {
+                    bucket: bucket.to_string(),
+                    key: key.to_string(),
+                }),
+                _ => Err(StorageError::InvalidUri(uri.to_string())),
+            };
+        }
+
+        if uri.contains("://") {
+            return Err(StorageError::InvalidUri(uri.to_string()));
+        }
+
+        Ok(Location::File(uri.into()))
+    }
+}
+
+impl Storage {
+    pub fn new(config: &Config) -> Result<Self, StorageError> {
+        let s3_region = match &config.s3_endpoint {
+            Some(endpoint) => Region::Custom {
+                region: config.s3_region.clone(),
+                endpoint: endpoint.clone(),
+            },
+            None => config
+                .s3_region
+                .parse()
+                .map_err(|_| StorageError::InvalidRegion(config.s3_region.clone()))?,
+        };
+
+        let s3_credentials = Credentials::new(
+            config.s3_access_key.as_deref(),
+            config.s3_secret_key.as_deref(),
+            None,
+            None,
+            None,
+        )?;
+
+        Ok(Self {
+            s3_region,
+            s3_credentials,
+        })
+    }
+
+    fn bucket(&self, name: &str) -> Result<Box<Bucket>, StorageError> {
+        // most of the S3-compatible storages only support path-style requests
+        let bucket = Bucket::new(name, self.s3_region.clone(), self.s3_credentials.clone())?
+            .with_path_style();
+        Ok(bucket)
+    }
+
+    pub async fn read(&self, uri: &str) -> Result<Vec<u8>, StorageError> {
+        match Location::parse(uri)? {
+            Location::File(path) => Ok(tokio::fs::read(path).await?),
+            Location::S3 { bucket, key } => {
+                let response = self.bucket(&bucket)?.get_object(key).await?;
+                Ok(response.to_vec())
+            }
+        }
+    }
+
+    pub async fn write(&self, uri: &str, bytes: &[u8]) -> Result<(), StorageError> {
+        match Location::parse(uri)? {
+            Location::File(path) => {
+                if let Some(parent) = path.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                Ok(tokio::fs::write(path, bytes).await?)
+            }
+            Location::S3 { bucket, key } => {
+                self.bucket(&bucket)?.put_object(key, bytes).await?;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::Location;
+
+    #[test]
+    fn test_parse_location() {
+        assert_eq!(
+            Location::parse("file:///images/a/b.png").unwrap(),
+            Location::File("/images/a/b.png".into())
+        );
+        assert_eq!(
+            Location::parse("images/b.png").unwrap(),
+            Location::File("images/b.png".into())
+        );
+        assert_eq!(
+            Location::parse("s3://picturas/a/b.png").unwrap(),
+            Location::S3 {
+                bucket: "picturas".into(),
+                key: "a/b.png".into()
+            }
+        );
+        assert!(Location::parse("s3://picturas").is_err());
+        assert!(Location::parse("http://example.com/b.png").is_err());
+    }
+}
@@ -4,6 +4,7 @@ use image::DynamicImage::ImageRgba8;
 use photon_rs::transform::SamplingFilter;
 use photon_rs::{helpers, PhotonImage, Rgba};
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use std::io::Cursor;
 
 #[derive(Deserialize, Serialize, Debug, PartialEq)]
@@ -21,6 +22,20 @@ pub enum Tool {
     Ocr,
 }
 
+/// The procedures handled by [`Tool`], in the order of its variants.
+pub const PROCEDURES: [&str; 10] = [
+    "crop",
+    "scale",
+    "addBorder",
+    "adjustBrightness",
+    "adjustContrast",
+    "rotate",
+    "blur",
+    "grayscale",
+    "binarize",
+    "ocr",
+];
+
 pub enum ToolApplyResult {
     Image(PhotonImage),
     Text(String),
@@ -43,6 +58,65 @@ impl Tool {
     }
 }
 
+/// The JSON Schema of the parameters of a procedure, announced to the services building pipelines
+/// so they can check the parameters before sending a request.
+pub fn parameters_schema(procedure: &str) -> Option<Value> {
+    let point = json!({
+        "type": "array",
+        "prefixItems": [
+            { "type": "integer", "minimum": 0 },
+            { "type": "integer", "minimum": 0 }
+        ],
+        "minItems": 2,
+        "maxItems": 2
+    });
+
+    let (properties, required) = match procedure {
+        "crop" => (
+            json!({ "start": point, "end": point }),
+            json!(["start", "end"]),
+        ),
+        "scale" => (
+            json!({
+                "x": { "type": "integer", "minimum": 0 },
+                "y": { "type": "integer", "minimum": 0 }
+            }),
+            json!(["x", "y"]),
+        ),
+        "addBorder" => (
+            json!({
+                "size": { "type": "integer", "minimum": 0 },
+                "color": {
+                    "type": "array",
+                    "items": { "type": "integer", "minimum": 0, "maximum": 255 },
+                    "minItems": 3,
+                    "maxItems": 3
+                }
+            }),
+            json!(["size", "color"]),
+        ),
+        "adjustBrightness" => (
+            json!({ "value": { "type": "number", "minimum": -1, "maximum": 1 } }),
+            json!(["value"]),
+        ),
+        "adjustContrast" => (json!({ "value": { "type": "number" } }), json!(["value"])),
+        "rotate" => (json!({ "angle": { "type": "number" } }), json!(["angle"])),
+        "blur" => (
+            json!({ "radius": { "type": "integer" } }),
+            json!(["radius"]),
+        ),
+        "grayscale" | "binarize" | "ocr" => (json!({}), json!([])),
+        _ => return None,
+    };
+
+    Some(json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false
+    }))
+}
+
 fn crop(image: PhotonImage, x1: u32, x2: u32, y1: u32, y2: u32) -> PhotonImage {
     let (x1, x2) = (x1.min(x2), x1.max(x2));
     let (y1, y2) = (y1.min(y2), y1.max(y2));
@@ -140,20 +214,7 @@ mod tests {
             Tool::Ocr,
         ];
 
-        let tools_names = [
-            "crop",
-            "scale",
-            "addBorder",
-            "adjustBrightness",
-            "adjustContrast",
-            "rotate",
-            "blur",
-            "grayscale",
-            "binarize",
-            "ocr",
-        ];
-
-        for (tool, tool_name) in tools.into_iter().zip(tools_names.iter()) {
+        for (tool, tool_name) in tools.into_iter().zip(crate::tools::PROCEDURES.iter()) {
             let result = tool.apply(image.clone()).unwrap();
             println!("Tool: {:?}", tool_name);
             match result {
@@ -166,4 +227,16 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_every_procedure_has_a_parameters_schema() {
+        use crate::tools::{parameters_schema, PROCEDURES};
+
+        for procedure in PROCEDURES {
+            let schema = parameters_schema(procedure).expect("Missing parameters schema");
+            assert_eq!(schema["type"], "object", "{procedure}");
+        }
+
+        assert!(parameters_schema("unknown").is_none());
+    }
 }
@@ -0,0 +1,6 @@
+-- the subscription tier of a user, issued in the access tokens
+CREATE TYPE user_role AS ENUM ('default', 'premium');
+
+ALTER TABLE users
+    ADD COLUMN role            user_role DEFAULT 'default' NOT NULL,
+    ADD COLUMN role_expires_at TIMESTAMPTZ;
@@ -36,6 +36,9 @@ pub struct Config {
     pub refresh_token_public_key: DecodingKey,
     #[arg(long, env, value_parser = load_encoding_key_from_file)]
     pub refresh_token_private_key: EncodingKey,
+    /// The token the other services send to call the internal endpoints.
+    #[arg(long, env, value_parser = load_secret_from_file)]
+    pub internal_token: String,
 }
 
 fn parse_duration(s: &str) -> Result<Duration, std::num::ParseIntError> {
@@ -49,3 +52,7 @@ fn load_decoding_key_from_file(path: &str) -> Result<DecodingKey, std::io::Error
 fn load_encoding_key_from_file(path: &str) -> Result<EncodingKey, std::io::Error> {
     Ok(EncodingKey::from_rsa_pem(&std::fs::read(path)?).unwrap())
 }
+
+fn load_secret_from_file(path: &str) -> Result<String, std::io::Error> {
+    Ok(std::fs::read_to_string(path)?.trim().to_string())
+}
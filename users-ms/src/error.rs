@@ -5,6 +5,7 @@ use axum::Json;
 use serde::Serialize;
 use thiserror::Error;
 use tracing::error;
+use uuid::Uuid;
 
 #[derive(Debug, Error)]
 pub enum AppError {
@@ -12,6 +13,8 @@ pub enum AppError {
     EmailAlreadyInUse(String),
     #[error("email not found: {0}")]
     EmailNotFound(String),
+    #[error("user not found: {0}")]
+    UserNotFound(Uuid),
     #[error(transparent)]
     ValidationError(#[from] validator::ValidationErrors),
     #[error(transparent)]
@@ -60,6 +63,11 @@ impl IntoResponse for AppError {
                 format!("Email not found: {}", email),
                 None,
             ),
+            AppError::UserNotFound(uuid) => (
+                StatusCode::NOT_FOUND,
+                format!("User not found: {}", uuid),
+                None,
+            ),
             AppError::InvalidPassword => (
                 StatusCode::UNAUTHORIZED,
                 "Invalid password".to_string(),
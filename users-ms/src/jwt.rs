@@ -1,6 +1,6 @@
 use crate::error::AppError::InvalidToken;
 use crate::error::AppResult;
-use crate::user::User;
+use crate::user::{User, UserRole};
 use crate::AppState;
 use chrono::Utc;
 use jsonwebtoken::{Algorithm, DecodingKey, Header, Validation};
@@ -13,6 +13,7 @@ pub struct AccessTokenClaims {
     pub sub: Uuid,
     pub name: String,
     pub email: String,
+    pub role: UserRole,
     pub token_id: Uuid,
     pub exp: i64,
 }
@@ -32,6 +33,7 @@ pub fn create_access_token(state: &AppState, token_id: Uuid, user: &User) -> App
         sub: user.uuid,
         name: user.name.clone(),
         email: user.email.clone(),
+        role: user.current_role(),
         token_id,
         exp: expiration,
     };
@@ -1,4 +1,5 @@
 use crate::error::{AppError, AppResult};
+use crate::user::UserRole;
 use crate::{jwt, password, redis, user, AppState};
 use axum::extract::State;
 use axum::http::{header, HeaderMap};
@@ -7,7 +8,7 @@ use axum::routing::{get, post};
 use axum::{debug_handler, Json, Router};
 use axum_extra::extract::cookie::Cookie;
 use axum_extra::extract::CookieJar;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use tower_http::trace::TraceLayer;
 use uuid::Uuid;
 use validator::Validate;
@@ -20,12 +21,16 @@ pub fn router(state: AppState) -> Router {
         .route("/api/v1/users/me", get(get_current_user))
         .route("/api/v1/users/logout", post(logout_user))
         .route("/api/v1/users/changepassword", post(change_password))
+        // called by the subscriptions service with the internal token, the gateway doesn't route
+        // `/internal` requests
+        .route("/internal/v1/users/role", post(update_user_role))
         .with_state(state)
         .layer(TraceLayer::new_for_http())
 }
 
 const ACCESS_TOKEN_COOKIE_NAME: &str = "access_token";
 const REFRESH_TOKEN_COOKIE_NAME: &str = "refresh_token";
+const INTERNAL_TOKEN_HEADER_NAME: &str = "x-internal-token";
 
 #[derive(serde::Deserialize, Validate)]
 pub struct RegisterRequest {
@@ -299,6 +304,49 @@ async fn change_password(
     ))
 }
 
+#[derive(serde::Deserialize)]
+struct UpdateUserRoleRequest {
+    user_id: Uuid,
+    role: UserRole,
+    /// When the subscription ends, in UTC.
+    expires_on: Option<NaiveDateTime>,
+}
+
+#[derive(serde::Serialize)]
+struct UpdateUserRoleResponse {
+    uuid: Uuid,
+    role: UserRole,
+    role_expires_at: Option<DateTime<Utc>>,
+}
+
+/// Sets the role of a user when their subscription changes. The access tokens issued from now
+/// on carry it.
+#[debug_handler]
+async fn update_user_role(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<UpdateUserRoleRequest>,
+) -> AppResult<Json<UpdateUserRoleResponse>> {
+    let token = headers
+        .get(INTERNAL_TOKEN_HEADER_NAME)
+        .ok_or(AppError::Unauthorized)?;
+    if !constant_time_eq(token.as_bytes(), state.config.internal_token.as_bytes()) {
+        return Err(AppError::Unauthorized);
+    }
+
+    let role_expires_at = request.expires_on.map(|expires_on| expires_on.and_utc());
+
+    if !user::update_role(request.user_id, request.role, role_expires_at, &state).await? {
+        return Err(AppError::UserNotFound(request.user_id));
+    }
+
+    Ok(Json(UpdateUserRoleResponse {
+        uuid: request.user_id,
+        role: request.role,
+        role_expires_at,
+    }))
+}
+
 fn append_access_token_cookie(
     header_map: &mut HeaderMap,
     access_token: &str,
@@ -328,3 +376,8 @@ fn append_refresh_token_cookie(
         refresh_cookie.to_string().parse().unwrap(),
     );
 }
+
+/// Compares the tokens in a time that doesn't depend on where they differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -4,7 +4,9 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "user_role", rename_all = "lowercase")]
 pub enum UserRole {
     Default,
     Premium,
@@ -17,22 +19,39 @@ pub struct User {
     pub email: String,
     pub password: String,
     pub created_at: DateTime<Utc>,
+    pub role: UserRole,
+    /// When the subscription the role comes from ends, if it does.
+    pub role_expires_at: Option<DateTime<Utc>>,
+}
+
+impl User {
+    /// The role of the user now, the default one once the subscription ended.
+    pub fn current_role(&self) -> UserRole {
+        match self.role_expires_at {
+            Some(expires_at) if expires_at <= Utc::now() => UserRole::Default,
+            _ => self.role,
+        }
+    }
 }
 
 pub async fn get_user_from_email(email: String, state: &AppState) -> AppResult<Option<User>> {
-    Ok(
-        sqlx::query_as!(User, "SELECT * FROM users WHERE email = $1", email)
-            .fetch_optional(&state.pg_pool)
-            .await?,
+    Ok(sqlx::query_as!(
+        User,
+        r#"SELECT uuid, name, email, password, created_at, role as "role: UserRole", role_expires_at FROM users WHERE email = $1"#,
+        email
     )
+    .fetch_optional(&state.pg_pool)
+    .await?)
 }
 
 pub async fn get_user_by_uuid(uuid: Uuid, state: &AppState) -> AppResult<Option<User>> {
-    Ok(
-        sqlx::query_as!(User, "SELECT * FROM users WHERE uuid = $1", uuid)
-            .fetch_optional(&state.pg_pool)
-            .await?,
+    Ok(sqlx::query_as!(
+        User,
+        r#"SELECT uuid, name, email, password, created_at, role as "role: UserRole", role_expires_at FROM users WHERE uuid = $1"#,
+        uuid
     )
+    .fetch_optional(&state.pg_pool)
+    .await?)
 }
 
 pub async fn register_user(user: RegisterRequest, state: &AppState) -> AppResult<User> {
@@ -44,6 +63,8 @@ pub async fn register_user(user: RegisterRequest, state: &AppState) -> AppResult
         email: user.email,
         password,
         created_at: Utc::now(),
+        role: UserRole::Default,
+        role_expires_at: None,
     };
 
     sqlx::query!(
@@ -77,3 +98,23 @@ pub async fn change_password(
 
     Ok(())
 }
+
+/// Sets the role of a user, until `expires_at` if given.
+/// Returns false if there is no such user.
+pub async fn update_role(
+    user_uuid: Uuid,
+    role: UserRole,
+    expires_at: Option<DateTime<Utc>>,
+    state: &AppState,
+) -> AppResult<bool> {
+    let result = sqlx::query!(
+        "UPDATE users SET role = $1, role_expires_at = $2 WHERE uuid = $3",
+        role as UserRole,
+        expires_at,
+        user_uuid
+    )
+    .execute(&state.pg_pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}